@@ -0,0 +1,346 @@
+//! `Copy` representations of the board, the pockets and the castling rights, stored in a [`RetroBoard`](crate::RetroBoard).
+
+use shakmaty::{attacks, Bitboard, Board, ByColor, ByRole, Color, Piece, Role, Square};
+
+use crate::{ParseRetroPocketError, RetroPockets};
+
+/// Same as [`Board`], without the occupied squares and the black pieces which are recomputed from the roles and the white pieces.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub(crate) struct CompactBoard {
+    by_role: ByRole<Bitboard>,
+    white: Bitboard,
+}
+
+impl CompactBoard {
+    #[inline]
+    pub fn by_role(&self, role: Role) -> Bitboard {
+        *self.by_role.get(role)
+    }
+
+    #[inline]
+    pub fn by_color(&self, color: Color) -> Bitboard {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.occupied() ^ self.white,
+        }
+    }
+
+    #[inline]
+    pub fn occupied(&self) -> Bitboard {
+        self.by_role.pawn
+            | self.by_role.knight
+            | self.by_role.bishop
+            | self.by_role.rook
+            | self.by_role.queen
+            | self.by_role.king
+    }
+
+    #[inline]
+    pub fn steppers(&self) -> Bitboard {
+        self.by_role.pawn | self.by_role.knight | self.by_role.king
+    }
+
+    #[inline]
+    pub fn rooks_and_queens(&self) -> Bitboard {
+        self.by_role.rook | self.by_role.queen
+    }
+
+    #[inline]
+    pub fn bishops_and_queens(&self) -> Bitboard {
+        self.by_role.bishop | self.by_role.queen
+    }
+
+    #[inline]
+    pub fn king_of(&self, color: Color) -> Option<Square> {
+        (self.by_role.king & self.by_color(color)).single_square()
+    }
+
+    #[inline]
+    pub fn role_at(&self, sq: Square) -> Option<Role> {
+        if self.occupied().contains(sq) {
+            self.by_role.find(|r| r.contains(sq))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn piece_at(&self, sq: Square) -> Option<Piece> {
+        self.role_at(sq).map(|role| Piece {
+            color: Color::from_white(self.white.contains(sq)),
+            role,
+        })
+    }
+
+    #[inline]
+    pub fn discard_piece_at(&mut self, sq: Square) {
+        if !self.occupied().contains(sq) {
+            return;
+        }
+        for bb in self.by_role.iter_mut() {
+            bb.discard(sq);
+        }
+        self.white.discard(sq);
+    }
+
+    #[inline]
+    pub fn set_piece_at(&mut self, sq: Square, piece: Piece) {
+        self.discard_piece_at(sq);
+        self.by_role.get_mut(piece.role).add(sq);
+        if piece.color == Color::White {
+            self.white.add(sq);
+        }
+    }
+
+    #[inline]
+    pub fn remove_piece_at(&mut self, sq: Square) -> Option<Piece> {
+        let piece = self.piece_at(sq);
+        self.discard_piece_at(sq);
+        piece
+    }
+
+    /// Pieces of `attacker` attacking `sq`, like [`Board::attacks_to`].
+    #[inline]
+    pub fn attacks_to(&self, sq: Square, attacker: Color, occupied: Bitboard) -> Bitboard {
+        self.by_color(attacker)
+            & ((attacks::rook_attacks(sq, occupied) & self.rooks_and_queens())
+                | (attacks::bishop_attacks(sq, occupied) & self.bishops_and_queens())
+                | (attacks::knight_attacks(sq) & self.by_role.knight)
+                | (attacks::king_attacks(sq) & self.by_role.king)
+                | (attacks::pawn_attacks(!attacker, sq) & self.by_role.pawn))
+    }
+
+    /// Applies the same transformation to every bitboard, see [`Bitboard::flip_vertical`] and the like.
+    pub fn transform(&mut self, f: fn(Bitboard) -> Bitboard) {
+        for bb in self.by_role.iter_mut() {
+            *bb = f(*bb);
+        }
+        self.white = f(self.white);
+    }
+}
+
+impl From<&Board> for CompactBoard {
+    fn from(board: &Board) -> Self {
+        Self {
+            by_role: ByRole::new_with(|role| board.by_role(role)),
+            white: board.white(),
+        }
+    }
+}
+
+impl From<CompactBoard> for Board {
+    fn from(board: CompactBoard) -> Self {
+        Board::from_bitboards(
+            board.by_role,
+            ByColor::new_with(|color| board.by_color(color)),
+        )
+    }
+}
+
+/// Same as [`RetroPockets`], with 4 bits per counter: both colors fit in 6 bytes, read as the 48 lowest bits of a `u64`.
+///
+/// The counters of a color are stored by [`Role`] order, the unpromotion one taking the place of the King.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub(crate) struct CompactPockets([u8; 6]);
+
+impl CompactPockets {
+    /// Largest value of a counter.
+    pub const MAX: u8 = 15;
+
+    #[inline]
+    fn shift(color: Color, role: Role) -> u32 {
+        color.fold_wb(0, 24) + 4 * (role as u32 - 1)
+    }
+
+    #[inline]
+    fn bits(self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes[..6].copy_from_slice(&self.0);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Number of pieces of that role in the pocket of `color`, the King being the unpromotion counter.
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn counter(self, color: Color, role: Role) -> u8 {
+        ((self.bits() >> Self::shift(color, role)) & 0xf) as u8
+    }
+
+    #[inline]
+    fn set_counter(&mut self, color: Color, role: Role, value: u8) {
+        assert!(value <= Self::MAX, "Pocket counter overflow");
+        let shift = Self::shift(color, role);
+        let bits = (self.bits() & !(0xf << shift)) | (u64::from(value) << shift);
+        self.0.copy_from_slice(&bits.to_le_bytes()[..6]);
+    }
+
+    /// Number of pieces of that role in the pocket of `color`, always 0 for the King.
+    #[inline]
+    pub fn get(self, color: Color, role: Role) -> u8 {
+        if role == Role::King {
+            0
+        } else {
+            self.counter(color, role)
+        }
+    }
+
+    /// Roles in the pocket of `color`, in [`Role`] order.
+    #[inline]
+    pub fn roles(self, color: Color) -> impl Iterator<Item = Role> {
+        Role::ALL[..5]
+            .iter()
+            .copied()
+            .filter(move |&role| self.counter(color, role) > 0)
+    }
+
+    #[inline]
+    pub fn unpromotion(self, color: Color) -> u8 {
+        self.counter(color, Role::King)
+    }
+
+    /// # Panics
+    /// Panics if the pocket does not contain that role, or if it is the King.
+    #[inline]
+    pub fn decr(&mut self, color: Color, role: Role) {
+        assert!(role != Role::King, "Cannot uncapture king");
+        let value = self
+            .get(color, role)
+            .checked_sub(1)
+            .expect("Attempt to decrement a pocket role whose value is already 0");
+        self.set_counter(color, role, value);
+    }
+
+    /// # Panics
+    /// Panics if called with the King.
+    #[inline]
+    pub fn incr(&mut self, color: Color, role: Role) {
+        assert!(role != Role::King, "Cannot uncapture king");
+        self.set_counter(color, role, self.get(color, role) + 1);
+    }
+
+    #[inline]
+    pub fn decr_unpromotion(&mut self, color: Color) {
+        self.set_counter(color, Role::King, self.unpromotion(color) - 1);
+    }
+
+    #[inline]
+    pub fn incr_unpromotion(&mut self, color: Color) {
+        self.set_counter(color, Role::King, self.unpromotion(color) + 1);
+    }
+}
+
+impl TryFrom<&RetroPockets> for CompactPockets {
+    type Error = ParseRetroPocketError;
+
+    fn try_from(pockets: &RetroPockets) -> Result<Self, Self::Error> {
+        let mut compact = Self::default();
+        for color in Color::ALL {
+            let pocket = pockets.color(color);
+            for role in Role::ALL {
+                let value = if role == Role::King {
+                    pocket.unpromotion
+                } else {
+                    pocket.get(role)
+                };
+                if value > Self::MAX {
+                    return Err(ParseRetroPocketError);
+                }
+                compact.set_counter(color, role, value);
+            }
+        }
+        Ok(compact)
+    }
+}
+
+impl From<CompactPockets> for RetroPockets {
+    fn from(compact: CompactPockets) -> Self {
+        let mut pockets = Self::default();
+        for color in Color::ALL {
+            let pocket = pockets.color_mut(color);
+            for role in Role::ALL {
+                if role == Role::King {
+                    pocket.unpromotion = compact.unpromotion(color);
+                } else {
+                    for _ in 0..compact.get(color, role) {
+                        pocket.incr(role);
+                    }
+                }
+            }
+        }
+        pockets
+    }
+}
+
+/// Same as the castling rights of a [`Setup`](shakmaty::Setup), which are always on the backranks:
+/// the files of the white rooks are stored in the low byte, and those of the black rooks in the high byte.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub(crate) struct CompactCastlingRights(u16);
+
+impl From<Bitboard> for CompactCastlingRights {
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn from(rooks: Bitboard) -> Self {
+        Self(((rooks.0 & 0xff) | ((rooks.0 >> 48) & 0xff00)) as u16)
+    }
+}
+
+impl From<CompactCastlingRights> for Bitboard {
+    #[inline]
+    fn from(compact: CompactCastlingRights) -> Self {
+        Bitboard(u64::from(compact.0 & 0xff) | (u64::from(compact.0 & 0xff00) << 48))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Color::{Black, White};
+
+    use super::*;
+
+    #[test]
+    fn test_compact_pockets() {
+        let pockets = RetroPockets::from_str("PPPPPPPPNNBBRRQ8", "NQ").unwrap();
+        let mut compact = CompactPockets::try_from(&pockets).unwrap();
+        assert_eq!(RetroPockets::from(compact), pockets);
+        assert_eq!(compact.get(White, Role::Pawn), 8);
+        assert_eq!(compact.unpromotion(White), 8);
+        assert_eq!(compact.get(White, Role::King), 0);
+        assert_eq!(
+            compact.roles(Black).collect::<Vec<_>>(),
+            [Role::Knight, Role::Queen]
+        );
+        compact.decr(Black, Role::Knight);
+        compact.incr_unpromotion(Black);
+        assert_eq!(
+            RetroPockets::from(compact),
+            RetroPockets::from_str("PPPPPPPPNNBBRRQ8", "Q1").unwrap()
+        );
+        assert!(
+            CompactPockets::try_from(&RetroPockets::from_str(&"Q".repeat(16), "").unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_compact_board() {
+        let board = Board::from_ascii_board_fen(b"r3k2r/8/8/3pP3/8/8/8/R3K2R").unwrap();
+        let mut compact = CompactBoard::from(&board);
+        assert_eq!(Board::from(compact), board);
+        compact.transform(Bitboard::flip_vertical);
+        let mut flipped = board.clone();
+        flipped.flip_vertical();
+        assert_eq!(Board::from(compact), flipped);
+    }
+
+    #[test]
+    fn test_compact_castling_rights() {
+        for rooks in [
+            Bitboard::EMPTY,
+            Bitboard::CORNERS,
+            Bitboard::from(Square::B1) | Square::G8,
+            Bitboard::from(Square::C8),
+        ] {
+            assert_eq!(Bitboard::from(CompactCastlingRights::from(rooks)), rooks);
+        }
+    }
+}
@@ -6,14 +6,28 @@
 #![doc = include_str!("../README.md")]
 
 mod unmove;
-pub use crate::unmove::{MoveKind, UnMove, UnMoveList};
+pub use crate::unmove::{
+    MoveKind, MoveKindMask, PackedUnMove, PackedUnMoveList, UnMove, UnMoveFilter, UnMoveList,
+    UnMoveSink,
+};
 
 mod retroboard;
-pub use crate::retroboard::{perft, RetroBoard};
+#[cfg(feature = "rayon")]
+pub use crate::retroboard::par_perft;
+pub use crate::retroboard::{
+    count_unique_predecessors, hashed_perft, perft, perft_divide, perft_stats, unique_predecessors,
+    IllegalUnMove, Inconsistency, InconsistencyKind, LegalUnMoves, PerftStats,
+    PositionLimitExceeded, RetroBoard, RetroContext, Undo,
+};
+
+mod compact;
 
 mod retropocket;
 pub use crate::retropocket::{ParseRetroPocketError, RetroPocket, RetroPockets};
 
+#[cfg(feature = "verification")]
+pub mod verification;
+
 /// Using the re-exported version of shakmaty will ensure no version clashes
 pub mod shakmaty {
     pub use shakmaty::*;
@@ -1,31 +1,174 @@
 use std::{
     cmp::Ordering,
+    collections::HashSet,
+    convert::identity,
+    error::Error,
     fmt,
     hash::{Hash, Hasher},
+    iter::FusedIterator,
+    num::{NonZeroU16, NonZeroU32},
+    str::FromStr,
 };
 
 use shakmaty::{
     attacks,
-    fen::ParseFenError,
-    Bitboard, Board, CastlingMode, Chess, Color,
+    fen::{Epd, ParseFenError},
+    zobrist::{Zobrist64, ZobristValue},
+    Bitboard, Board, ByRole, Castles, CastlingMode, CastlingSide, Chess, Color,
     Color::{Black, White},
-    FromSetup, Piece, Position, PositionError, Rank, Role, Setup, Square,
+    EnPassantMode, File, FromSetup, Move, Piece, Position, PositionError, Rank, Role, Setup,
+    Square,
 };
 
 use crate::{
-    MoveKind::{EnPassant, Normal, UnPromotion, Uncapture},
-    RetroPockets, UnMove, UnMoveList,
+    compact::{CompactBoard, CompactCastlingRights, CompactPockets},
+    MoveKind::{EnPassant, Normal, UnCastling, UnPromotion, Uncapture},
+    MoveKindMask, RetroPocket, RetroPockets, UnMove, UnMoveFilter, UnMoveList, UnMoveSink,
 };
 
+/// Reason why an [`UnMove`] is illegal, see [`RetroBoard::check_unmove`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum IllegalUnMove {
+    /// White is to move on the first move of the game, nothing can be retracted.
+    StartOfGame,
+    /// The from square does not contain a piece of the side retracting.
+    EmptyFromSquare,
+    /// The king or the rook has castling rights, so it cannot have moved.
+    CastlingRights,
+    /// Pawn moves, uncaptures and unpromotions are not possible while the halfmove clock is running.
+    HalfmoveClock,
+    /// A pawn cannot be uncaptured on the first or eighth rank.
+    PawnOnBackRank,
+    /// The opponent's pocket does not contain the piece to uncapture.
+    EmptyPocket,
+    /// The pocket of the side retracting has no unpromotion left.
+    NoUnPromotion,
+    /// With an en passant square, only the pawn double push can be retracted.
+    EnPassantSquare,
+    /// The piece cannot go back to the target square.
+    CannotReach,
+    /// Moving a blocker away exposes the opponent's king.
+    ExposesKing,
+    /// The opponent's king would be in check.
+    GivesCheck,
+    /// The opponent's king is in check and the unmove does not resolve it.
+    CheckNotResolved,
+    /// The king would have castled out of, through or into check.
+    CastlingThroughCheck,
+}
+
+impl fmt::Display for IllegalUnMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::StartOfGame => "no unmove at the start of the game",
+            Self::EmptyFromSquare => "no piece to unmove on the from square",
+            Self::CastlingRights => "piece with castling rights cannot be unmoved",
+            Self::HalfmoveClock => "breaking unmove while the halfmove clock is running",
+            Self::PawnOnBackRank => "pawn cannot be uncaptured on a back rank",
+            Self::EmptyPocket => "no such piece in the opponent's pocket",
+            Self::NoUnPromotion => "no unpromotion left in the pocket",
+            Self::EnPassantSquare => "only the pawn double push can be retracted",
+            Self::CannotReach => "piece cannot go back to the target square",
+            Self::ExposesKing => "moving the blocker exposes the opponent's king",
+            Self::GivesCheck => "unmove gives check to the opponent's king",
+            Self::CheckNotResolved => "opponent's king is still in check",
+            Self::CastlingThroughCheck => "king castled out of, through or into check",
+        })
+    }
+}
+
+impl Error for IllegalUnMove {}
+
+/// Reason why the forward move of an unmove does not lead back to the position, see [`RetroBoard::check_consistency`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum InconsistencyKind {
+    /// The position after the unmove is not a valid `shakmaty` position.
+    InvalidPosition,
+    /// The forward move is not legal in the position after the unmove.
+    IllegalMove,
+    /// Playing the forward move leads to another position.
+    DifferentPosition,
+}
+
+impl fmt::Display for InconsistencyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidPosition => "invalid position after the unmove",
+            Self::IllegalMove => "illegal forward move after the unmove",
+            Self::DifferentPosition => "forward move leads to another position",
+        })
+    }
+}
+
+/// Unmove generated by [`RetroBoard::legal_unmoves`] whose forward move does not lead back to the position,
+/// see [`RetroBoard::check_consistency`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Inconsistency {
+    pub kind: InconsistencyKind,
+    pub unmove: UnMove,
+    /// Positions before and after [`RetroBoard::push`]ing the unmove, boxed to keep the error small.
+    pub positions: Box<(RetroBoard, RetroBoard)>,
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.unmove.to_retro_uci(), self.kind)
+    }
+}
+
+impl Error for Inconsistency {}
+
+/// What [`RetroBoard::push`] cannot recover from the unmove alone, to undo it with [`RetroBoard::pop`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Undo {
+    role: Role, // of the piece before being unpromoted
+    halfmoves: u8,
+    ep_square: Option<Square>,
+    castling_rights: CompactCastlingRights,
+    halfmove_clock: u16,
+    zobrist: u64,
+}
+
+impl Undo {
+    /// The forward [`Move`] undone by the unmove `m` that returned this [`Undo`] when pushed,
+    /// see [`RetroBoard::forward_move`] to get it before the push.
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::{Move, Role, Square}, RetroBoard, UnMove};
+    /// let mut r = RetroBoard::new("N3k3/8/8/8/8/8/8/4K3 b - - 0 1", "1", "B").unwrap();
+    /// let unmove = UnMove::from_retro_uci("UBa8b7").unwrap();
+    /// let undo = r.push(&unmove);
+    /// assert_eq!(
+    ///     undo.forward_move(&unmove),
+    ///     Move::Normal {
+    ///         role: Role::Pawn,
+    ///         from: Square::B7,
+    ///         capture: Some(Role::Bishop),
+    ///         to: Square::A8,
+    ///         promotion: Some(Role::Knight),
+    ///     }
+    /// );
+    /// ```
+    #[must_use]
+    pub fn forward_move(&self, m: &UnMove) -> Move {
+        forward_move(m, self.role)
+    }
+}
+
 /// A [`shakmaty::Board`] where [`Unmove`](crate::UnMove) are played and all legal [`Unmove`](crate::UnMove) can be generated.
 /// It is the user responsability to ensure that position is legal. Unreachable positions are considered legal, for example [this position](https://lichess.org/editor/3k4/2B1B3/8/8/8/8/5N2/3K4_b_-_-_0_1).
-#[derive(Clone)] // Copy?
+#[derive(Clone, Copy)]
 pub struct RetroBoard {
-    board: Board,
+    board: CompactBoard,
     retro_turn: Color,
-    pockets: RetroPockets,
+    pockets: CompactPockets,
     halfmoves: u8, // Number of plies since a breaking unmove has been done.
     ep_square: Option<Square>,
+    castling_rights: CompactCastlingRights, // rooks with castling rights, like `Setup::castling_rights`
+    castling_mode: CastlingMode,
+    halfmove_clock: u16, // Number of plies left which cannot be breaking unmoves, see `new_with_move_counters`.
+    fullmoves: Option<NonZeroU16>, // Only tracked if loaded from the fen, see `new_with_move_counters`.
+    zobrist: Zobrist64,            // Updated incrementally, see `zobrist_hash`.
 }
 
 impl RetroBoard {
@@ -35,11 +178,17 @@ impl RetroBoard {
     }
 
     /// Returns a new [`RetroBoard`] with defined [`RetroPocket`](crate::RetroPocket), see [`RetroPocket::from_str`](crate::RetroPocket) documentation
-    /// to see which string format is expected.
+    /// to see which string format is expected. Each pocket counter must be at most 15,
+    /// otherwise [`ParseFenError::InvalidPocket`] is returned.
+    /// Castling rights of the fen are kept, and the king and rooks concerned will never be unmoved.
+    /// As with [`FromSetup`], castling rights which do not match the position of the kings and rooks are discarded.
+    /// If castling rights are only valid in chess960, [`CastlingMode::Chess960`] is used, otherwise [`CastlingMode::Standard`].
+    /// A single string containing the pockets can also be parsed, see [`RetroBoard::from_str`].
     /// # Examples
     /// ```
     /// use retroboard::RetroBoard;
     /// let r = RetroBoard::new("3k4/8/8/8/8/8/8/2RKR3 w - - 0 1", "PNQ1", "7BBBB").unwrap();
+    /// assert!(RetroBoard::new("3k4/8/8/8/8/8/8/2RKR3 w - - 0 1", &"P".repeat(16), "").is_err());
     /// ```
     pub fn new(fen: &str, pocket_white: &str, pocket_black: &str) -> Result<Self, ParseFenError> {
         let fen_vec: Vec<&str> = fen.split(' ').collect();
@@ -55,137 +204,832 @@ impl RetroBoard {
                 .ok_or(ParseFenError::InvalidBoard)?
                 .as_bytes(),
         )?;
-        let pockets = RetroPockets::from_str(pocket_white, pocket_black)?;
+        let pockets =
+            CompactPockets::try_from(&RetroPockets::from_str(pocket_white, pocket_black)?)?;
         let ep_square = fen_vec
             .get(3)
             .and_then(|sq| Square::from_ascii(sq.as_bytes()).ok());
+        let (castling_rights, castling_mode) =
+            parse_castling_rights(&board, fen_vec.get(2).unwrap_or(&"-"))?;
         // It doesn't make sense to initialize halfmoves from the fen, since doing unmoves.
-        Ok(RetroBoard {
-            board,
+        // The move counters are only used as constraints when asked, see `new_with_move_counters`
+        let mut rboard = RetroBoard {
+            board: CompactBoard::from(&board),
             retro_turn,
             pockets,
             halfmoves: 0,
             ep_square,
-        })
-    }
-
-    pub fn push(&mut self, m: &UnMove) {
+            castling_rights: castling_rights.into(),
+            castling_mode,
+            halfmove_clock: 0,
+            fullmoves: None,
+            zobrist: Zobrist64::default(),
+        };
+        rboard.zobrist = rboard.compute_zobrist();
+        Ok(rboard)
+    }
+
+    /// Same as [`RetroBoard::new`], but the halfmove clock and the fullmove number of the fen are also loaded.
+    ///
+    /// A halfmove clock of N means the last N plies were neither captures nor pawn moves,
+    /// so the first N unmoves cannot be uncaptures, unpromotions, en-passant or pawn unmoves.
+    ///
+    /// A halfmove clock of 0 is deliberately left unconstrained: the first unmove is not forced to be a breaking one.
+    /// A clock of 0 is also found at the start of a game or in set up positions, without any capture or pawn move,
+    /// and the clock before a breaking unmove is unknown anyway.
+    ///
+    /// The fullmove number decreases each time a black move is retracted, and no unmove
+    /// is generated once white is to move on move 1, since that is the start of the game.
+    ///
+    /// Both counters must be at most 65535, otherwise [`ParseFenError::InvalidHalfmoveClock`]
+    /// or [`ParseFenError::InvalidFullmoves`] is returned.
+    /// # Examples
+    /// ```
+    /// use retroboard::RetroBoard;
+    /// let r = RetroBoard::new_with_move_counters("3k4/8/8/8/8/3P4/8/2RKR3 b - - 1 1", "", "Q").unwrap();
+    /// assert_eq!(r.halfmove_clock(), 1);
+    /// assert!(r.legal_unmoves().iter().all(|m| m.uncapture().is_none()));
+    ///
+    /// let start = RetroBoard::new_with_move_counters("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "", "").unwrap();
+    /// assert!(start.legal_unmoves().is_empty());
+    /// ```
+    pub fn new_with_move_counters(
+        fen: &str,
+        pocket_white: &str,
+        pocket_black: &str,
+    ) -> Result<Self, ParseFenError> {
+        let mut rboard = Self::new(fen, pocket_white, pocket_black)?;
+        let fen_vec: Vec<&str> = fen.split(' ').collect();
+        if let Some(halfmove_clock) = fen_vec.get(4) {
+            rboard.halfmove_clock = halfmove_clock
+                .parse()
+                .map_err(|_| ParseFenError::InvalidHalfmoveClock)?;
+        }
+        rboard.fullmoves = Some(
+            fen_vec
+                .get(5)
+                .map_or(Ok(1), |fullmoves| fullmoves.parse())
+                .ok()
+                .and_then(NonZeroU16::new)
+                .ok_or(ParseFenError::InvalidFullmoves)?,
+        );
+        Ok(rboard)
+    }
+
+    /// Play the unmove, without checking its legality.
+    /// The returned [`Undo`] can be given to [`RetroBoard::pop`] to take the unmove back.
+    /// # Panics
+    /// Can panic or corrupt the [`RetroBoard`] if the unmove is not legal, see [`RetroBoard::try_push`].
+    pub fn push(&mut self, m: &UnMove) -> Undo {
+        let mut undo = Undo {
+            role: Role::King,
+            halfmoves: self.halfmoves,
+            ep_square: self.ep_square,
+            castling_rights: self.castling_rights,
+            halfmove_clock: self.halfmove_clock,
+            zobrist: self.zobrist.0,
+        };
+        self.zobrist ^= Zobrist64::zobrist_for_white_turn();
+        if let Some(ep_square) = self.ep_square {
+            self.zobrist ^= Zobrist64::zobrist_for_en_passant_file(ep_square.file());
+        }
+        if m.is_uncastling() {
+            self.push_uncastling(m);
+            return undo;
+        }
         let moved_piece = self
             .board
             .remove_piece_at(m.from)
             .expect("Unmove: from square should contain a piece");
+        self.zobrist ^= Zobrist64::zobrist_for_piece(m.from, moved_piece);
+        undo.role = moved_piece.role;
+        assert!(
+            self.halfmove_clock == 0
+                || !(moved_piece.role == Role::Pawn
+                    || m.uncapture().is_some()
+                    || m.is_unpromotion()),
+            "Unmove: breaking unmove while the halfmove clock is running"
+        );
+        self.halfmove_clock = self.halfmove_clock.saturating_sub(1);
         self.halfmoves += 1;
         self.ep_square = None;
 
         if let Some(role) = m.uncapture() {
             self.halfmoves = 0;
-            self.board.set_piece_at(
-                m.uncapture_square().unwrap(),
-                Piece {
-                    role,
-                    color: !self.retro_turn,
-                },
-            );
-            self.pockets.color_mut(!self.retro_turn).decr(role);
-        };
-        if m.is_unpromotion() {
+            let uncaptured = Piece {
+                role,
+                color: !self.retro_turn,
+            };
+            let square = m.uncapture_square().unwrap();
+            self.board.set_piece_at(square, uncaptured);
+            self.zobrist ^= Zobrist64::zobrist_for_piece(square, uncaptured);
+            self.zobrist ^= pocket_zobrist(!self.retro_turn, role, self.pockets);
+            self.pockets.decr(!self.retro_turn, role);
+            self.zobrist ^= pocket_zobrist(!self.retro_turn, role, self.pockets);
+        }
+        let unmoved_piece = if m.is_unpromotion() {
             self.halfmoves = 0;
-            self.board.set_piece_at(
-                m.to,
-                Piece {
-                    role: Role::Pawn,
-                    color: self.retro_turn,
-                },
-            );
-            self.pockets.color_mut(self.retro_turn).unpromotion -= 1;
+            self.zobrist ^= pocket_zobrist(self.retro_turn, Role::King, self.pockets);
+            self.pockets.decr_unpromotion(self.retro_turn);
+            self.zobrist ^= pocket_zobrist(self.retro_turn, Role::King, self.pockets);
+            self.retro_turn.pawn()
         } else {
-            self.board.set_piece_at(m.to, moved_piece);
+            moved_piece
         };
+        self.board.set_piece_at(m.to, unmoved_piece);
+        self.zobrist ^= Zobrist64::zobrist_for_piece(m.to, unmoved_piece);
         if m.is_en_passant() {
             self.ep_square = Some(m.from);
+            self.zobrist ^= Zobrist64::zobrist_for_en_passant_file(m.from.file());
         }
+        self.decr_fullmoves();
         self.retro_turn = !self.retro_turn;
+        undo
+    }
+
+    /// Take back the last unmove played with [`RetroBoard::push`], restoring the [`RetroBoard`] exactly.
+    /// # Panics
+    /// Can panic or corrupt the [`RetroBoard`] if the unmove or the [`Undo`] are not the ones of the last [`RetroBoard::push`].
+    /// # Examples
+    /// ```
+    /// use retroboard::{RetroBoard, UnMove};
+    /// let mut r = RetroBoard::new("N3k3/8/8/8/8/8/8/4K3 b - - 0 1", "1", "B").unwrap();
+    /// let before = r.clone();
+    /// let m = UnMove::from_retro_uci("UBa8b7").unwrap();
+    /// let undo = r.push(&m);
+    /// r.pop(&m, undo);
+    /// assert_eq!(r, before);
+    /// ```
+    pub fn pop(&mut self, m: &UnMove, undo: Undo) {
+        self.retro_turn = !self.retro_turn;
+        if self.retro_turn == Black {
+            self.fullmoves = self
+                .fullmoves
+                .map(|fullmoves| NonZeroU16::new(fullmoves.get() + 1).unwrap());
+        }
+        self.halfmoves = undo.halfmoves;
+        self.ep_square = undo.ep_square;
+        self.castling_rights = undo.castling_rights;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.zobrist = Zobrist64(undo.zobrist);
+        if m.is_uncastling() {
+            let side = m.castling_side().unwrap();
+            let king = self
+                .board
+                .remove_piece_at(m.from)
+                .expect("Pop: king should be back on its square");
+            let rook = self
+                .board
+                .remove_piece_at(m.to)
+                .expect("Pop: rook should be back on its square");
+            self.board.set_piece_at(side.king_to(self.retro_turn), king);
+            self.board.set_piece_at(side.rook_to(self.retro_turn), rook);
+            return;
+        }
+        self.board
+            .remove_piece_at(m.to)
+            .expect("Pop: to square should contain the unmoved piece");
+        if let Some(role) = m.uncapture() {
+            self.board.discard_piece_at(m.uncapture_square().unwrap());
+            self.pockets.incr(!self.retro_turn, role);
+        }
+        self.board.set_piece_at(
+            m.from,
+            Piece {
+                role: undo.role,
+                color: self.retro_turn,
+            },
+        );
+        if m.is_unpromotion() {
+            self.pockets.incr_unpromotion(self.retro_turn);
+        }
+    }
+
+    fn push_uncastling(&mut self, m: &UnMove) {
+        let side = m.castling_side().unwrap();
+        let king = self
+            .board
+            .remove_piece_at(side.king_to(self.retro_turn))
+            .expect("Uncastling: king should be on its castling square");
+        let rook = self
+            .board
+            .remove_piece_at(side.rook_to(self.retro_turn))
+            .expect("Uncastling: rook should be on its castling square");
+        self.board.set_piece_at(m.from, king);
+        self.board.set_piece_at(m.to, rook);
+        self.zobrist ^= Zobrist64::zobrist_for_piece(side.king_to(self.retro_turn), king)
+            ^ Zobrist64::zobrist_for_piece(side.rook_to(self.retro_turn), rook)
+            ^ Zobrist64::zobrist_for_piece(m.from, king)
+            ^ Zobrist64::zobrist_for_piece(m.to, rook)
+            ^ Zobrist64::zobrist_for_castling_right(self.retro_turn, side);
+        self.castling_rights = self.castling_rights().with(m.to).into();
+        self.halfmove_clock = self.halfmove_clock.saturating_sub(1);
+        self.halfmoves += 1;
+        self.ep_square = None;
+        self.decr_fullmoves();
+        self.retro_turn = !self.retro_turn;
+    }
+
+    /// Play the unmove if it is legal, otherwise the [`RetroBoard`] is left untouched.
+    /// # Errors
+    /// Returns why the unmove is illegal, see [`RetroBoard::check_unmove`].
+    /// # Examples
+    /// ```
+    /// use retroboard::{IllegalUnMove, RetroBoard, UnMove};
+    /// let mut r = RetroBoard::new_no_pockets("3k4/8/8/8/8/8/8/2RKR3 b - - 0 1").unwrap();
+    /// assert_eq!(
+    ///     r.try_push(&UnMove::from_retro_uci("Qc1c2").unwrap()),
+    ///     Err(IllegalUnMove::EmptyPocket)
+    /// );
+    /// assert!(r.try_push(&UnMove::from_retro_uci("c1c2").unwrap()).is_ok());
+    /// ```
+    pub fn try_push(&mut self, m: &UnMove) -> Result<Undo, IllegalUnMove> {
+        self.check_unmove(m)?;
+        Ok(self.push(m))
+    }
+
+    /// Returns `true` if the unmove is one of [`RetroBoard::legal_unmoves`].
+    #[must_use]
+    pub fn is_legal_unmove(&self, m: &UnMove) -> bool {
+        self.is_legal_unmove_with(&self.retro_context(), m)
     }
 
-    pub fn pseudo_legal_unmoves(&self, moves: &mut UnMoveList) {
+    /// Same as [`RetroBoard::is_legal_unmove`], reusing the context of the position.
+    /// Only the unmoves with the same squares are generated.
+    #[must_use]
+    pub fn is_legal_unmove_with(&self, ctx: &RetroContext, m: &UnMove) -> bool {
+        let mut moves = UnMoveList::new();
+        self.legal_unmoves_with(
+            ctx,
+            &UnMoveFilter {
+                from: m.from.into(),
+                to: m.to.into(),
+                ..UnMoveFilter::ALL
+            },
+            &mut moves,
+        );
+        moves.contains(m)
+    }
+
+    /// Check that the unmove is one of [`RetroBoard::legal_unmoves`].
+    /// # Errors
+    /// Returns the first reason found why the unmove is illegal.
+    /// # Examples
+    /// ```
+    /// use retroboard::{IllegalUnMove, RetroBoard, UnMove};
+    /// let r = RetroBoard::new_no_pockets("3k4/8/8/8/8/8/8/2RKR3 b - - 0 1").unwrap();
+    /// let check = |uci| r.check_unmove(&UnMove::from_retro_uci(uci).unwrap());
+    /// assert_eq!(check("c1c2"), Ok(()));
+    /// assert_eq!(check("a1a2"), Err(IllegalUnMove::EmptyFromSquare));
+    /// assert_eq!(check("c1d2"), Err(IllegalUnMove::CannotReach));
+    /// assert_eq!(check("c1c8"), Err(IllegalUnMove::GivesCheck));
+    /// ```
+    pub fn check_unmove(&self, m: &UnMove) -> Result<(), IllegalUnMove> {
+        let ctx = self.retro_context();
+        if self.is_legal_unmove_with(&ctx, m) {
+            Ok(())
+        } else {
+            Err(self.illegal_reason(&ctx, m))
+        }
+    }
+
+    /// The forward [`Move`] undone by the unmove, to be called before [`RetroBoard::push`]ing it.
+    /// The move is legal in the [`Chess`] position obtained from the [`RetroBoard`] after the push,
+    /// and playing it leads back to the current position.
+    ///
+    /// Once the unmove is pushed, the promotion role is no longer on the board,
+    /// so use [`Undo::forward_move`] with the [`Undo`] returned by the push instead.
+    /// # Panics
+    /// Panics if the from square of the unmove is empty.
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::{Move, Role, Square}, RetroBoard, UnMove};
+    /// let r = RetroBoard::new("N3k3/8/8/8/8/8/8/4K3 b - - 0 1", "1", "B").unwrap();
+    /// assert_eq!(
+    ///     r.forward_move(&UnMove::from_retro_uci("UBa8b7").unwrap()),
+    ///     Move::Normal {
+    ///         role: Role::Pawn,
+    ///         from: Square::B7,
+    ///         capture: Some(Role::Bishop),
+    ///         to: Square::A8,
+    ///         promotion: Some(Role::Knight),
+    ///     }
+    /// );
+    /// ```
+    #[must_use]
+    pub fn forward_move(&self, m: &UnMove) -> Move {
+        let role = if m.is_uncastling() {
+            Role::King
+        } else {
+            self.board
+                .role_at(m.from)
+                .expect("Unmove: from square should contain a piece")
+        };
+        forward_move(m, role)
+    }
+
+    /// Check that for every unmove of [`RetroBoard::legal_unmoves`], playing its [`RetroBoard::forward_move`]
+    /// in the position after [`RetroBoard::push`]ing it gives back the current position.
+    /// Returns the first unmove for which it is not the case, with the positions before and after it.
+    /// # Examples
+    /// ```
+    /// use retroboard::RetroBoard;
+    ///
+    /// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+    /// assert!(r.check_consistency().is_ok());
+    /// ```
+    pub fn check_consistency(&self) -> Result<(), Inconsistency> {
+        for unmove in self.legal_unmoves() {
+            let m = self.forward_move(&unmove);
+            let mut after = *self;
+            after.push(&unmove);
+            if let Err(kind) = self.round_trip(&after, &m) {
+                return Err(Inconsistency {
+                    kind,
+                    unmove,
+                    positions: Box::new((*self, after)),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn round_trip(&self, after: &RetroBoard, m: &Move) -> Result<(), InconsistencyKind> {
+        let pos = Chess::from_setup(Setup::from(*after), self.castling_mode)
+            .or_else(PositionError::ignore_impossible_check)
+            .or_else(PositionError::ignore_impossible_material)
+            .map_err(|_| InconsistencyKind::InvalidPosition)?;
+        if !pos.is_legal(m) {
+            return Err(InconsistencyKind::IllegalMove);
+        }
+        let back = pos.play(m).expect("Legal move");
+        // the en passant square and the halfmove clock are only known if set
+        if back.board() == &self.board()
+            && back.turn() == !self.retro_turn
+            && back.castles().castling_rights() == self.castling_rights()
+            && self
+                .ep_square
+                .map_or(true, |sq| back.ep_square(EnPassantMode::Always) == Some(sq))
+            && (self.halfmove_clock == 0 || back.halfmoves() == self.halfmove_clock())
+        {
+            Ok(())
+        } else {
+            Err(InconsistencyKind::DifferentPosition)
+        }
+    }
+
+    /// Mirrors the checks done by `pseudo_legal_unmoves` then `legal_unmoves`, for an unmove known to be illegal.
+    fn illegal_reason(&self, ctx: &RetroContext, m: &UnMove) -> IllegalUnMove {
+        if self.is_start_of_game() {
+            return IllegalUnMove::StartOfGame;
+        }
+        if m.is_uncastling() {
+            if (self.castling_rights() & self.retro_turn.backrank()).any() {
+                return IllegalUnMove::CastlingRights;
+            }
+        } else {
+            let piece = match self.board.piece_at(m.from) {
+                Some(piece) if piece.color == self.retro_turn => piece,
+                _ => return IllegalUnMove::EmptyFromSquare,
+            };
+            if self.castling_pieces().contains(m.from) {
+                return IllegalUnMove::CastlingRights;
+            }
+            if self.halfmove_clock > 0
+                && (piece.role == Role::Pawn || m.uncapture().is_some() || m.is_unpromotion())
+            {
+                return IllegalUnMove::HalfmoveClock;
+            }
+            if let Some(role) = m.uncapture() {
+                if role == Role::Pawn && Bitboard::BACKRANKS.contains(m.from) {
+                    return IllegalUnMove::PawnOnBackRank;
+                }
+                if self.pockets.get(!self.retro_turn, role) == 0 {
+                    return IllegalUnMove::EmptyPocket;
+                }
+            }
+            if m.is_unpromotion() && self.pockets.unpromotion(self.retro_turn) == 0 {
+                return IllegalUnMove::NoUnPromotion;
+            }
+        }
+        let mut moves = UnMoveList::new();
+        self.pseudo_legal_unmoves(&mut moves);
+        if !moves.contains(m) {
+            return if self.ep_square.is_some() {
+                IllegalUnMove::EnPassantSquare
+            } else {
+                IllegalUnMove::CannotReach
+            };
+        }
+        let safety = if m.is_uncastling() {
+            self.uncastling_safety(ctx, m)
+        } else if ctx.checkers.more_than_one() {
+            if self.does_unmove_give_check(ctx.king, m) {
+                Err(IllegalUnMove::GivesCheck)
+            } else {
+                Err(IllegalUnMove::CheckNotResolved)
+            }
+        } else {
+            self.safety(ctx, m)
+        };
+        // the only unmoves left are uncastling or pseudo-legal ones that `handle_two_checkers` rejected
+        safety.err().unwrap_or(IllegalUnMove::CheckNotResolved)
+    }
+
+    fn compute_zobrist(&self) -> Zobrist64 {
+        let mut zobrist = Zobrist64::default();
+        for sq in self.occupied() {
+            zobrist ^= Zobrist64::zobrist_for_piece(sq, self.board.piece_at(sq).unwrap());
+        }
+        // like shakmaty, depends on the side to move
+        if self.retro_turn == Black {
+            zobrist ^= Zobrist64::zobrist_for_white_turn();
+        }
+        for rook in self.castling_rights() {
+            let color = Color::from_white(rook.rank() == Rank::First);
+            let side = CastlingSide::from_king_side(rook.file() > self.king_of(color).file());
+            zobrist ^= Zobrist64::zobrist_for_castling_right(color, side);
+        }
+        if let Some(ep_square) = self.ep_square {
+            zobrist ^= Zobrist64::zobrist_for_en_passant_file(ep_square.file());
+        }
+        for color in Color::ALL {
+            for role in Role::ALL {
+                zobrist ^= pocket_zobrist(color, role, self.pockets);
+            }
+        }
+        zobrist
+    }
+
+    /// Fullmove number is decremented when a black move is retracted, must be called before updating `retro_turn`.
+    fn decr_fullmoves(&mut self) {
+        if self.retro_turn == Black {
+            self.fullmoves = self.fullmoves.map(|fullmoves| {
+                NonZeroU16::new(fullmoves.get() - 1)
+                    .expect("Unmove: cannot retract a move before the start of the game")
+            });
+        }
+    }
+
+    /// When the fullmove number is tracked, the start of the game is reached once white is to move on move 1.
+    #[inline]
+    pub(crate) fn is_start_of_game(&self) -> bool {
+        self.retro_turn == Black && self.fullmoves.map_or(false, |f| f.get() == 1)
+    }
+
+    pub fn pseudo_legal_unmoves<S: UnMoveSink>(&self, moves: &mut S) {
+        self.gen_filtered(&UnMoveFilter::ALL, None, moves);
+    }
+
+    /// Pseudo legal unmoves kept by `filter`, the masks are applied during generation.
+    fn gen_filtered<S: UnMoveSink>(
+        &self,
+        filter: &UnMoveFilter,
+        ctx: Option<&RetroContext>,
+        moves: &mut S,
+    ) {
+        if self.is_start_of_game() {
+            return;
+        }
         // then there is only move possible
         if let Some(sq) = self.ep_square {
-            // ep square always on the third or sixth rank, so offseting is fine
-            moves.push(UnMove::new(
-                sq.offset(self.retro_turn.fold_wb(8, -8)).unwrap(), // from
-                sq.offset(self.retro_turn.fold_wb(-8, 8)).unwrap(), // to
-                Normal,
-            ))
-        } else {
-            self.gen_pieces(moves);
-            self.gen_unpromotion(moves);
-            self.gen_pawns(moves);
-            self.gen_en_passant(moves, Bitboard::FULL);
+            // a pawn double push is not possible while the halfmove clock is running
+            if self.halfmove_clock == 0 {
+                // ep square always on the third or sixth rank, so offseting is fine
+                let m = UnMove::new(
+                    sq.offset(self.retro_turn.fold_wb(8, -8)).unwrap(), // from
+                    sq.offset(self.retro_turn.fold_wb(-8, 8)).unwrap(), // to
+                    Normal,
+                );
+                if filter.matches(&m, Role::Pawn)
+                    && ctx.map_or(true, |ctx| self.safety(ctx, &m).is_ok())
+                {
+                    moves.push(m);
+                }
+            }
+            return;
         }
+        let mut filter = *filter;
+        // only unmoves which do not break the halfmove clock are possible while it is running
+        if self.halfmove_clock > 0 {
+            filter.kinds = filter.kinds & (MoveKindMask::QUIET | MoveKindMask::UNCASTLING);
+            filter.roles.pawn = false;
+        }
+        // the king goes back to an empty square when uncastling
+        let uncastling_from = filter.from;
+        let mut from = Bitboard::EMPTY;
+        for role in Role::ALL {
+            if *filter.roles.get(role) {
+                from |= self.board.by_role(role);
+            }
+        }
+        filter.from &= from;
+        self.gen_pieces(&filter, ctx, moves);
+        if filter.kinds.contains(MoveKindMask::UNPROMOTION) {
+            self.gen_unpromotion(&filter, ctx, moves);
+        }
+        if filter.roles.pawn {
+            self.gen_pawns(&filter, ctx, moves);
+            if filter.kinds.contains(MoveKindMask::EN_PASSANT) {
+                self.gen_en_passant(&filter, ctx, moves);
+            }
+        }
+        if filter.roles.king && filter.kinds.contains(MoveKindMask::UNCASTLING) {
+            self.gen_uncastling(&mut |m: UnMove| {
+                if uncastling_from.contains(m.from)
+                    && filter.to.contains(m.to)
+                    && ctx.map_or(true, |ctx| self.uncastling_safety(ctx, &m).is_ok())
+                {
+                    moves.push(m);
+                }
+            });
+        }
+    }
+
+    fn gen_stage<S: UnMoveSink>(&self, stage: Stage, ctx: &RetroContext, moves: &mut S) {
+        self.gen_filtered(&stage.filter(), Some(ctx), moves);
     }
 
     /// Generate legal unmoves, which are all the pseudo legal unmoves which do not put the opponent's king in check.
     /// If the opponent's king is in check at the beginning of our turn, the only legal unmoves are those which stop it from being in check.
     #[must_use]
     pub fn legal_unmoves(&self) -> UnMoveList {
+        let mut moves = UnMoveList::new();
+        self.legal_unmoves_into(&mut moves);
+        moves
+    }
+
+    /// Same as [`RetroBoard::legal_unmoves`], but the unmoves are given to `sink` as they are generated.
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::{Bitboard, Square}, RetroBoard, UnMove};
+    /// let r = RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/3NK3 b - - 0 1").unwrap();
+    /// let mut targets = Bitboard::EMPTY;
+    /// r.legal_unmoves_into(&mut targets);
+    /// assert!(targets.contains(Square::C3) && targets.contains(Square::F2));
+    ///
+    /// let mut knight_unmoves = 0;
+    /// r.legal_unmoves_into(&mut |m: UnMove| {
+    ///     if m.from == Square::D1 {
+    ///         knight_unmoves += 1
+    ///     }
+    /// });
+    /// assert_eq!(knight_unmoves, 4);
+    /// ```
+    pub fn legal_unmoves_into<S: UnMoveSink>(&self, sink: &mut S) {
+        self.legal_unmoves_with(&self.retro_context(), &UnMoveFilter::ALL, sink);
+    }
+
+    /// Legal unmoves kept by `filter`. Generation is restricted by the filter, rather than
+    /// filtering [`RetroBoard::legal_unmoves`] afterwards.
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::{Bitboard, Square}, MoveKindMask, RetroBoard, UnMoveFilter};
+    /// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+    /// let from_f8 = UnMoveFilter {
+    ///     from: Square::F8.into(),
+    ///     ..UnMoveFilter::ALL
+    /// };
+    /// let unmoves = r.legal_unmoves_filtered(&from_f8);
+    /// assert!(unmoves.iter().all(|m| m.from == Square::F8));
+    ///
+    /// // the knight on f8 came from d7, e6 or g6, or was a pawn on e7, f7 or g7 before promoting
+    /// let knight_unmoves = UnMoveFilter {
+    ///     kinds: MoveKindMask::QUIET | MoveKindMask::UNCAPTURE,
+    ///     ..from_f8
+    /// };
+    /// assert_eq!(
+    ///     r.legal_unmoves_filtered(&knight_unmoves).iter().map(|m| m.to).collect::<Bitboard>(),
+    ///     Bitboard::from(Square::D7) | Square::E6 | Square::G6
+    /// );
+    /// let unpromotions = UnMoveFilter {
+    ///     kinds: MoveKindMask::UNPROMOTION,
+    ///     ..from_f8
+    /// };
+    /// assert_eq!(
+    ///     r.legal_unmoves_filtered(&unpromotions).iter().map(|m| m.to).collect::<Bitboard>(),
+    ///     Bitboard::from(Square::E7) | Square::F7 | Square::G7
+    /// );
+    /// ```
+    #[must_use]
+    pub fn legal_unmoves_filtered(&self, filter: &UnMoveFilter) -> UnMoveList {
+        let mut moves = UnMoveList::new();
+        self.legal_unmoves_with(&self.retro_context(), filter, &mut moves);
+        moves
+    }
+
+    /// Legal unmoves kept by `filter`, given to `sink` as they are generated.
+    /// `ctx` must be the [`RetroBoard::retro_context`] of the position, and can be reused across calls.
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::Square, RetroBoard, UnMoveFilter};
+    /// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+    /// let ctx = r.retro_context();
+    /// let mut count = 0;
+    /// for from in r.us() {
+    ///     let filter = UnMoveFilter {
+    ///         from: from.into(),
+    ///         ..UnMoveFilter::ALL
+    ///     };
+    ///     r.legal_unmoves_with(&ctx, &filter, &mut |_| count += 1);
+    /// }
+    /// assert_eq!(count, r.count_legal_unmoves());
+    /// ```
+    pub fn legal_unmoves_with<S: UnMoveSink>(
+        &self,
+        ctx: &RetroContext,
+        filter: &UnMoveFilter,
+        sink: &mut S,
+    ) {
         // supposing the opponent's king is not in check at the beginning of our retro_turn
-        let mut moves: UnMoveList = UnMoveList::new();
-        let checkers = self.checkers(!self.retro_turn);
-        let blockers = self.slider_blockers(self.us(), self.king_of(!self.retro_turn));
-        let nb_checkers = checkers.count();
-        match nb_checkers.cmp(&2) {
-            Ordering::Greater => return moves, // no unmoves possible
+        if self.is_start_of_game() {
+            return;
+        }
+        match ctx.checkers.count().cmp(&2) {
+            Ordering::Greater => (), // no unmoves possible
             Ordering::Equal => {
-                if checkers.is_subset(self.board.steppers()) {
-                    return moves;
-                };
+                if ctx.checkers.is_subset(self.board.steppers()) {
+                    return;
+                }
 
                 // should work if two sliders or one slider one stepper.
                 // If there is one stepper, the slider should be the furthest piece.
                 // However when the two pieces are at equal distance from the king, we must consider
                 // the stepper as the closest piece
-                let (closest_checker, furthest_checker) = closest_and_further_square(
-                    checkers,
-                    self.king_of(!self.retro_turn),
-                    self.board.steppers(),
+                let (closest_checker, furthest_checker) =
+                    closest_and_further_square(ctx.checkers, ctx.king, self.board.steppers());
+
+                // uncastling cannot be the answer, since castling can only give check with the rook
+                // there are very few unmoves, so they are generated without filter
+                self.handle_two_checkers(
+                    ctx,
+                    closest_checker,
+                    furthest_checker,
+                    &mut |m: UnMove| {
+                        if filter.matches(&m, self.board.role_at(m.from).unwrap()) {
+                            sink.push(m);
+                        }
+                    },
                 );
+            }
+            // 1 or no checker.
+            Ordering::Less => self.gen_filtered(filter, Some(ctx), sink),
+        }
+    }
 
-                if !blockers.contains(closest_checker) {
-                    self.handle_two_checkers(closest_checker, furthest_checker, &mut moves)
+    /// Check and pin information about the king of the side not to retro-move,
+    /// needed to generate legal unmoves, see [`RetroBoard::legal_unmoves_with`].
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::Square, RetroBoard};
+    /// let r = RetroBoard::new_no_pockets("3k4/8/8/8/8/8/3R4/3K4 b - - 0 1").unwrap();
+    /// let ctx = r.retro_context();
+    /// assert_eq!(ctx.king(), Square::D8);
+    /// assert_eq!(ctx.checkers(), Square::D2.into());
+    /// assert!(ctx.evasions().contains(Square::D5));
+    /// ```
+    #[must_use]
+    pub fn retro_context(&self) -> RetroContext {
+        let king = self.king_of(!self.retro_turn);
+        let checkers = self.checkers(!self.retro_turn);
+        let evasions = if checkers.is_empty() {
+            Bitboard::FULL
+        } else {
+            match checkers.single_square() {
+                Some(checker) if !self.board.steppers().contains(checker) => {
+                    attacks::between(checker, king)
                 }
+                _ => Bitboard::EMPTY,
             }
-            Ordering::Less => {
-                // 1 or no checker.
-                self.pseudo_legal_unmoves(&mut moves);
-                moves.retain(|m| self.is_safe(m, blockers, checkers.first()));
-            }
+        };
+        RetroContext {
+            king,
+            checkers,
+            blockers: self.slider_blockers(self.us(), king),
+            evasions,
         }
+    }
 
-        moves
+    /// Number of legal unmoves, without storing them.
+    /// # Examples
+    /// ```
+    /// use retroboard::RetroBoard;
+    /// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+    /// assert_eq!(r.count_legal_unmoves(), 24);
+    /// ```
+    #[must_use]
+    pub fn count_legal_unmoves(&self) -> usize {
+        let mut count = 0;
+        self.legal_unmoves_into(&mut |_| count += 1);
+        count
     }
 
-    fn handle_two_checkers(
+    /// Same as [`RetroBoard::legal_unmoves`], but the unmoves are generated in stages
+    /// and only when they are reached, so stopping early saves work.
+    /// The stages are piece unmoves, piece uncaptures, unpromotions, pawn unmoves, en passant and uncastling.
+    /// # Examples
+    /// ```
+    /// use retroboard::RetroBoard;
+    /// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+    /// let first_uncapture = r.legal_unmoves_iter().find(|m| m.is_uncapture());
+    /// assert!(first_uncapture.is_some());
+    /// assert_eq!(r.legal_unmoves_iter().count(), r.legal_unmoves().len());
+    /// ```
+    #[must_use]
+    pub fn legal_unmoves_iter(&self) -> LegalUnMoves<'_> {
+        let ctx = self.retro_context();
+        let mut iter = LegalUnMoves {
+            rboard: self,
+            stage: Stage::Done,
+            moves: UnMoveList::new(),
+            index: 0,
+            ctx,
+        };
+        // with several checkers the few legal unmoves are generated at once
+        if ctx.checkers.more_than_one() {
+            self.legal_unmoves_with(&ctx, &UnMoveFilter::ALL, &mut iter.moves);
+        } else {
+            iter.stage = Stage::Pieces;
+        }
+        iter
+    }
+
+    /// Squares the piece on `from` can go back to as a `role` without the opponent's king being left in check,
+    /// first when `from` is left empty, then when an uncaptured piece is left on it.
+    #[inline]
+    fn legal_targets(
+        &self,
+        ctx: Option<&RetroContext>,
+        from: Square,
+        role: Role,
+    ) -> (Bitboard, Bitboard) {
+        let ctx = match ctx {
+            Some(ctx) => ctx,
+            None => return (Bitboard::FULL, Bitboard::FULL),
+        };
+        // the checker is the only piece which can move away from the check instead of blocking it
+        let evasions = if ctx.checkers.contains(from) {
+            Bitboard::FULL
+        } else {
+            ctx.evasions
+        };
+        // a piece gives check from the squares a piece of the same role attacks from the king
+        let checking = Piece {
+            color: !self.retro_turn,
+            role,
+        };
+        let mut quiet = evasions & !attacks::attacks(ctx.king, checking, self.occupied() ^ from);
+        if ctx.blockers.contains(from) {
+            quiet &= attacks::ray(from, ctx.king);
+        }
+        let uncapture = evasions & !attacks::attacks(ctx.king, checking, self.occupied());
+        (quiet, uncapture)
+    }
+
+    fn handle_two_checkers<S: UnMoveSink>(
         &self,
+        ctx: &RetroContext,
         closest_checker: Square,
         furthest_checker: Square,
-        moves: &mut UnMoveList,
+        sink: &mut S,
     ) {
+        if self.castling_pieces().contains(closest_checker) {
+            return;
+        }
+        let closest_is_blocker = ctx.blockers.contains(closest_checker);
+        let mut moves = UnMoveList::new();
         let from_piece = self.board.piece_at(closest_checker).unwrap();
-        let target = attacks::between(self.king_of(!self.retro_turn), furthest_checker);
+        let target = attacks::between(ctx.king, furthest_checker);
         // the closest piece must come into the way of the further one
-        if let Some(to) =
-            (retro_attacks(closest_checker, from_piece, self.occupied()) & target).first()
-        {
-            if from_piece.role != Role::Pawn {
+        // if it is also a blocker, only an uncaptured piece left behind can keep blocking
+        for to in retro_attacks(closest_checker, from_piece, self.occupied()) & target {
+            if from_piece.role != Role::Pawn && !closest_is_blocker {
                 moves.push(UnMove::new(closest_checker, to, Normal));
             }
-            self.gen_en_passant(moves, target);
-            self.gen_uncaptures(closest_checker, to, false, moves);
-            if Bitboard::BACKRANKS.contains(closest_checker) {
-                self.gen_uncaptures(closest_checker, to, true, moves);
-            };
-            // we do not check if the move itself gives check before
-            moves.retain(|m| !self.does_unmove_give_check(m));
+            if self.halfmove_clock == 0 {
+                self.gen_uncaptures(closest_checker, to, false, &mut moves);
+            }
+        }
+        if self.halfmove_clock == 0 {
+            if self.pockets.unpromotion(self.retro_turn) > 0
+                && self.retro_turn.relative_rank(Rank::Eighth) == closest_checker.rank()
+            {
+                let mut unpromotions = UnMoveList::new();
+                self.gen_unpromotion_on(closest_checker, target, None, &mut unpromotions);
+                moves.extend(
+                    unpromotions
+                        .into_iter()
+                        .filter(|m| m.uncapture().is_some() || !closest_is_blocker),
+                );
+            }
+            // the uncaptured pawn can block the furthest checker, so both checks are looked at directly
+            self.gen_en_passant(&UnMoveFilter::ALL, Some(ctx), &mut moves);
+        }
+        // we do not check if the move itself gives check before
+        for m in moves {
+            if !self.does_unmove_give_check(ctx.king, &m) {
+                sink.push(m);
+            }
         }
     }
 
@@ -207,37 +1051,76 @@ impl RetroBoard {
         blockers
     }
 
-    fn is_safe(&self, unmove: &UnMove, blockers: Bitboard, checker: Option<Square>) -> bool {
-        let king = self.king_of(!self.retro_turn);
+    fn safety(&self, ctx: &RetroContext, unmove: &UnMove) -> Result<(), IllegalUnMove> {
+        let king = ctx.king;
         // If we remove a blocker without letting a piece behing we'll put the king in check, so the unmove is invalid
-        if !unmove.is_uncapture()
-            && blockers.contains(unmove.from)
+        if unmove.uncapture_square() != Some(unmove.from)
+            && ctx.blockers.contains(unmove.from)
             && !attacks::aligned(unmove.from, unmove.to, king)
         {
-            return false;
+            return Err(IllegalUnMove::ExposesKing);
         }
 
         // check if the unmove attack the king
-        if self.does_unmove_give_check(unmove) {
-            return false;
+        if self.does_unmove_give_check(king, unmove) {
+            return Err(IllegalUnMove::GivesCheck);
         }
 
         // no checker we can end here
-        if checker.is_none() {
-            return true;
-        }
+        let checker = match ctx.checkers.first() {
+            Some(checker) => checker,
+            None => return Ok(()),
+        };
 
         // if the checker does not move and is not a slider, then at the end the king will still be in check
-        if self.board.steppers().contains(checker.unwrap()) && checker.unwrap() != unmove.from {
-            return false;
-        }
-        // Now we know the checker is a slider and either it moves away to a square where it does not put the king in check (we already checked if the destination square gives check, so only left to check if it is the checker)
+        // Otherwise we know the checker is a slider and either it moves away to a square where it does not put the king in check (we already checked if the destination square gives check, so only left to check if it is the checker)
         // or it does not move, and then we need to check if a piece goes between it.
-        checker.unwrap() == unmove.from
-            || attacks::between(checker.unwrap(), king).contains(unmove.to)
+        if checker == unmove.from
+            || (!self.board.steppers().contains(checker)
+                && attacks::between(checker, king).contains(unmove.to))
+        {
+            Ok(())
+        } else {
+            Err(IllegalUnMove::CheckNotResolved)
+        }
     }
 
-    fn does_unmove_give_check(&self, unmove: &UnMove) -> bool {
+    /// Uncastling moves two pieces so the whole position before castling is checked.
+    /// The king must not have castled out of, through or into check,
+    /// and the opponent's king must not be in check before castling.
+    fn uncastling_safety(&self, ctx: &RetroContext, unmove: &UnMove) -> Result<(), IllegalUnMove> {
+        let side = unmove.castling_side().unwrap();
+        let king_to = side.king_to(self.retro_turn);
+        let mut board = self.board;
+        let king = board.remove_piece_at(king_to).unwrap();
+        let rook = board
+            .remove_piece_at(side.rook_to(self.retro_turn))
+            .unwrap();
+        board.set_piece_at(unmove.from, king);
+        board.set_piece_at(unmove.to, rook);
+        let occupied = board.occupied();
+        if !(attacks::between(unmove.from, king_to)
+            .with(unmove.from)
+            .into_iter()
+            .all(|sq| {
+                board
+                    .attacks_to(sq, !self.retro_turn, occupied ^ unmove.from)
+                    .is_empty()
+            })
+            && self
+                .board
+                .attacks_to(king_to, !self.retro_turn, self.occupied())
+                .is_empty())
+        {
+            Err(IllegalUnMove::CastlingThroughCheck)
+        } else if board.attacks_to(ctx.king, self.retro_turn, occupied).any() {
+            Err(IllegalUnMove::GivesCheck)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn does_unmove_give_check(&self, king: Square, unmove: &UnMove) -> bool {
         (attacks::attacks(
             unmove.to,
             if unmove.is_unpromotion() {
@@ -246,19 +1129,19 @@ impl RetroBoard {
                 self.board.piece_at(unmove.from).unwrap()
             },
             self.occupied()
-                ^ if unmove.is_uncapture() {
+                ^ if unmove.uncapture_square() == Some(unmove.from) {
                     Bitboard::EMPTY
                 } else {
                     unmove.from.into()
                 },
-        ) & self.king_of(!self.retro_turn))
-        .any()
+        ) & king)
+            .any()
     }
 
     #[inline]
     #[must_use]
-    pub fn board(&self) -> &Board {
-        &self.board
+    pub fn board(&self) -> Board {
+        self.board.into()
     }
 
     #[inline]
@@ -303,55 +1186,125 @@ impl RetroBoard {
         self.board.king_of(color).unwrap()
     }
 
+    /// Castling rights and the en passant square are cleared, the king, rooks and pawns are no longer where they need to be.
+    fn transform(&mut self, f: fn(Bitboard) -> Bitboard) {
+        self.board.transform(f);
+        self.castling_rights = CompactCastlingRights::default();
+        self.ep_square = None;
+        self.zobrist = self.compute_zobrist();
+    }
+
+    /// Castling rights and the en passant square are cleared.
     #[inline]
     pub fn flip_vertical(&mut self) {
-        self.board.flip_vertical()
+        self.transform(Bitboard::flip_vertical);
     }
 
+    /// Castling rights are cleared, the en passant square is flipped along with the pawns.
     #[inline]
     pub fn flip_horizontal(&mut self) {
-        self.board.flip_horizontal()
+        self.board.transform(Bitboard::flip_horizontal);
+        self.castling_rights = CompactCastlingRights::default();
+        self.ep_square = self.ep_square.map(Square::flip_horizontal);
+        self.zobrist = self.compute_zobrist();
     }
 
+    /// Castling rights and the en passant square are cleared.
     #[inline]
     pub fn flip_diagonal(&mut self) {
-        self.board.flip_diagonal()
+        self.transform(Bitboard::flip_diagonal);
     }
 
+    /// Castling rights and the en passant square are cleared.
     #[inline]
     pub fn flip_anti_diagonal(&mut self) {
-        self.board.flip_anti_diagonal()
+        self.transform(Bitboard::flip_anti_diagonal);
     }
 
+    /// Castling rights and the en passant square are cleared.
     #[inline]
     pub fn rotate_90(&mut self) {
-        self.board.rotate_90()
+        self.transform(Bitboard::rotate_90);
     }
 
+    /// Castling rights and the en passant square are cleared.
     #[inline]
     pub fn rotate_180(&mut self) {
-        self.board.rotate_180()
+        self.transform(Bitboard::rotate_180);
     }
 
+    /// Castling rights and the en passant square are cleared.
     #[inline]
     pub fn rotate_270(&mut self) {
-        self.board.rotate_270()
+        self.transform(Bitboard::rotate_270);
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn castling_rights(&self) -> Bitboard {
+        self.castling_rights.into()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn castling_mode(&self) -> CastlingMode {
+        self.castling_mode
+    }
+
+    /// Number of plies which can still only be retracted by non-breaking unmoves,
+    /// see [`RetroBoard::new_with_move_counters`]. 0 if there is no such constraint.
+    #[inline]
+    #[must_use]
+    pub fn halfmove_clock(&self) -> u32 {
+        u32::from(self.halfmove_clock)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn pockets(&self) -> RetroPockets {
+        self.pockets.into()
+    }
+
+    /// Square on which a pawn can be captured en passant, the last move can then only be a pawn double push.
+    #[inline]
+    #[must_use]
+    pub fn ep_square(&self) -> Option<Square> {
+        self.ep_square
+    }
+
+    /// Zobrist key of the position, updated incrementally by [`RetroBoard::push`].
+    /// Pieces, retro turn, castling rights, en passant square and the pockets of both colors are taken into account,
+    /// but not the move counters.
+    ///
+    /// It is built from the [`shakmaty::zobrist`] values, so it is stable across versions.
+    /// The unpromotion counter of a [`RetroPocket`] uses the values of the king pocket, which is never filled otherwise.
+    /// # Examples
+    /// ```
+    /// use retroboard::{RetroBoard, UnMove};
+    /// let mut r = RetroBoard::new("4k3/8/8/8/8/8/8/3NK3 b - - 0 1", "", "").unwrap();
+    /// let key = r.zobrist_hash();
+    /// let undo = r.push(&UnMove::from_retro_uci("d1c3").unwrap());
+    /// assert_ne!(r.zobrist_hash(), key);
+    /// r.pop(&UnMove::from_retro_uci("d1c3").unwrap(), undo);
+    /// assert_eq!(r.zobrist_hash(), key);
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn zobrist_hash(&self) -> Zobrist64 {
+        self.zobrist
+    }
+
+    /// Fullmove number, see [`RetroBoard::new_with_move_counters`]. 1 if it is not tracked.
+    #[inline]
+    #[must_use]
+    pub fn fullmoves(&self) -> NonZeroU32 {
+        self.fullmoves
+            .map_or(NonZeroU32::new(1).unwrap(), NonZeroU32::from)
     }
 
     #[inline]
     fn epd(&self) -> String {
-        format!(
-            "{} {} - {}",
-            self.board.board_fen(Bitboard::EMPTY),
-            match self.retro_turn {
-                Black => "w",
-                White => "b",
-            },
-            self.ep_square.map_or_else(
-                || "-".to_string(),
-                |sq| format!("{sq:?}").to_ascii_lowercase()
-            )
-        )
+        Epd::from(Setup::from(*self)).to_string()
     }
 
     #[inline]
@@ -360,39 +1313,91 @@ impl RetroBoard {
             .attacks_to(self.king_of(color), !color, self.occupied())
     }
 
-    fn gen_unpromotion(&self, moves: &mut UnMoveList) {
-        if self.pockets.color(self.retro_turn).unpromotion > 0 {
-            for from in self.us() & self.retro_turn.relative_rank(Rank::Eighth) {
-                self.gen_unpromotion_on(from, moves);
+    fn gen_unpromotion<S: UnMoveSink>(
+        &self,
+        filter: &UnMoveFilter,
+        ctx: Option<&RetroContext>,
+        moves: &mut S,
+    ) {
+        if self.pockets.unpromotion(self.retro_turn) > 0 {
+            for from in self.us()
+                & !self.our(Role::King)
+                & self.retro_turn.relative_rank(Rank::Eighth)
+                & filter.from
+            {
+                self.gen_unpromotion_on(from, filter.to, ctx, moves);
             }
         }
     }
 
-    fn gen_unpromotion_on(&self, from: Square, moves: &mut UnMoveList) {
+    fn gen_unpromotion_on<S: UnMoveSink>(
+        &self,
+        from: Square,
+        target: Bitboard,
+        ctx: Option<&RetroContext>,
+        moves: &mut S,
+    ) {
+        let (quiet, uncapture) = self.legal_targets(ctx, from, Role::Pawn);
         let to = from
             .offset(self.retro_turn.fold_wb(-8, 8))
             .expect("We're in the eighth rank and going back so square exists");
-        if self.board.piece_at(to).is_none() {
+        if (target & quiet).contains(to) && self.board.piece_at(to).is_none() {
             moves.push(UnMove::new(from, to, UnPromotion(None)));
-        };
-        self.gen_pawn_uncaptures(from, true, moves);
+        }
+        self.gen_pawn_uncaptures(from, target & uncapture, true, moves);
     }
 
-    fn gen_pieces(&self, moves: &mut UnMoveList) {
-        for from in self.us() & !self.our(Role::Pawn) {
-            for to in attacks::attacks(from, self.board.piece_at(from).unwrap(), self.occupied())
-                & !self.occupied()
-            {
-                moves.push(UnMove::new(from, to, Normal));
-                self.gen_uncaptures(from, to, false, moves)
+    /// Squares of the rooks with castling rights and of their king, these pieces cannot have moved.
+    #[inline]
+    fn castling_pieces(&self) -> Bitboard {
+        let mut pieces = self.castling_rights();
+        for color in Color::ALL {
+            if (pieces & color.backrank()).any() {
+                pieces.add(self.king_of(color));
             }
         }
+        pieces
     }
 
-    fn gen_en_passant(&self, moves: &mut UnMoveList, target: Bitboard) {
-        if self.pockets.color(!self.retro_turn).pawn > 0 {
+    fn gen_pieces<S: UnMoveSink>(
+        &self,
+        filter: &UnMoveFilter,
+        ctx: Option<&RetroContext>,
+        moves: &mut S,
+    ) {
+        let quiets = filter.kinds.contains(MoveKindMask::QUIET);
+        let uncaptures = filter.kinds.contains(MoveKindMask::UNCAPTURE);
+        if !quiets && !uncaptures {
+            return;
+        }
+        for from in self.us() & !self.our(Role::Pawn) & !self.castling_pieces() & filter.from {
+            let piece = self.board.piece_at(from).unwrap();
+            let targets =
+                attacks::attacks(from, piece, self.occupied()) & !self.occupied() & filter.to;
+            let (quiet, uncapture) = self.legal_targets(ctx, from, piece.role);
+            if quiets {
+                for to in targets & quiet {
+                    moves.push(UnMove::new(from, to, Normal));
+                }
+            }
+            if uncaptures {
+                for to in targets & uncapture {
+                    self.gen_uncaptures(from, to, false, moves)
+                }
+            }
+        }
+    }
+
+    fn gen_en_passant<S: UnMoveSink>(
+        &self,
+        filter: &UnMoveFilter,
+        ctx: Option<&RetroContext>,
+        moves: &mut S,
+    ) {
+        if self.pockets.get(!self.retro_turn, Role::Pawn) > 0 {
             // pawns on the relative 6th rank with free space above AND below them
             let ep_pawns = self.our(Role::Pawn)
+                & filter.from
                 & self.retro_turn.relative_rank(Rank::Sixth)
                 & (!(self.occupied() & self.retro_turn.relative_rank(Rank::Fifth)))
                     .shift(self.retro_turn.fold_wb(8, -8))
@@ -400,67 +1405,151 @@ impl RetroBoard {
                     .shift(self.retro_turn.fold_wb(-8, 8));
 
             for from in ep_pawns {
-                for to in attacks::pawn_attacks(!self.retro_turn, from) & !self.occupied() & target
+                for to in
+                    attacks::pawn_attacks(!self.retro_turn, from) & !self.occupied() & filter.to
                 {
-                    moves.push(UnMove::new(from, to, EnPassant));
+                    if ctx.map_or(true, |ctx| self.is_en_passant_safe(ctx.king, from, to)) {
+                        moves.push(UnMove::new(from, to, EnPassant));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Three squares change with an en passant unmove, so instead of using the legality masks
+    /// the opponent's king is checked to be safe in the position after the unmove.
+    fn is_en_passant_safe(&self, king: Square, from: Square, to: Square) -> bool {
+        let uncaptured = Square::from_coords(from.file(), to.rank());
+        let occupied = self.occupied() ^ from ^ to ^ uncaptured;
+        (self.board.attacks_to(king, self.retro_turn, occupied) & !Bitboard::from(from)).is_empty()
+            && !attacks::pawn_attacks(self.retro_turn, to).contains(king)
+    }
+
+    fn gen_pawns<S: UnMoveSink>(
+        &self,
+        filter: &UnMoveFilter,
+        ctx: Option<&RetroContext>,
+        moves: &mut S,
+    ) {
+        let quiets = filter.kinds.contains(MoveKindMask::QUIET);
+        let uncaptures = filter.kinds.contains(MoveKindMask::UNCAPTURE);
+        let backward = self.retro_turn.fold_wb(-8, 8);
+        let second_rank = Bitboard::from(self.retro_turn.relative_rank(Rank::Second));
+        for from in self.our(Role::Pawn) & filter.from {
+            let (quiet, uncapture) = self.legal_targets(ctx, from, Role::Pawn);
+            if uncaptures && !second_rank.contains(from) {
+                self.gen_pawn_uncaptures(from, filter.to & uncapture, false, moves)
+            }
+            if !quiets {
+                continue;
+            }
+            let targets = filter.to & quiet;
+            // pawns on the second rank cannot go back, and the ones on the third can go back twice
+            if let Some(single) = from
+                .offset(backward)
+                .filter(|to| !Bitboard::BACKRANKS.contains(*to) && !self.occupied().contains(*to))
+            {
+                if targets.contains(single) {
+                    moves.push(UnMove::new(from, single, Normal));
+                }
+                if let Some(double) = single.offset(backward).filter(|to| {
+                    second_rank.contains(*to)
+                        && targets.contains(*to)
+                        && !self.occupied().contains(*to)
+                }) {
+                    moves.push(UnMove::new(from, double, Normal));
                 }
             }
         }
     }
 
-    fn gen_pawns(&self, moves: &mut UnMoveList) {
-        // generate pawn uncaptures
-        for from in
-            self.our(Role::Pawn) & !Bitboard::from(self.retro_turn.relative_rank(Rank::Second))
-        {
-            self.gen_pawn_uncaptures(from, false, moves)
+    fn gen_uncastling<S: UnMoveSink>(&self, moves: &mut S) {
+        // castling removes all castling rights of the side which castled
+        if (self.castling_rights() & self.retro_turn.backrank()).any() {
+            return;
         }
-
-        let single_moves =
-            self.our(Role::Pawn).shift(self.retro_turn.fold_wb(-8, 8)) & !self.occupied();
-
-        let double_moves = single_moves.shift(self.retro_turn.fold_wb(-8, 8))
-            & self.retro_turn.relative_rank(Rank::Second)
-            & !self.occupied();
-
-        for to in single_moves & !Bitboard::BACKRANKS {
-            if let Some(from) = to.offset(self.retro_turn.fold_wb(8, -8)) {
-                moves.push(UnMove::new(from, to, Normal));
+        let backrank = Bitboard::from(self.retro_turn.backrank());
+        for side in CastlingSide::ALL {
+            if self.board.piece_at(side.king_to(self.retro_turn)) != Some(self.retro_turn.king())
+                || self.board.piece_at(side.rook_to(self.retro_turn))
+                    != Some(self.retro_turn.rook())
+            {
+                continue;
             }
-        }
-
-        for to in double_moves {
-            if let Some(from) = to.offset(self.retro_turn.fold_wb(16, -16)) {
-                moves.push(UnMove::new(from, to, Normal));
+            match self.castling_mode {
+                CastlingMode::Standard => {
+                    let king_from = Square::from_coords(File::E, self.retro_turn.backrank());
+                    let rook_from = Square::from_coords(
+                        if side.is_queen_side() {
+                            File::A
+                        } else {
+                            File::H
+                        },
+                        self.retro_turn.backrank(),
+                    );
+                    if self.can_uncastle(side, king_from, rook_from) {
+                        moves.push(UnMove::new(king_from, rook_from, UnCastling));
+                    }
+                }
+                CastlingMode::Chess960 => {
+                    // the king cannot start in a corner, and the rook is on the side the king castled to
+                    for king_from in backrank & !Bitboard::CORNERS {
+                        for rook_from in backrank {
+                            if (rook_from > king_from) == side.is_king_side()
+                                && rook_from != king_from
+                                && self.can_uncastle(side, king_from, rook_from)
+                            {
+                                moves.push(UnMove::new(king_from, rook_from, UnCastling));
+                            }
+                        }
+                    }
+                }
             }
         }
     }
 
-    fn gen_pawn_uncaptures(&self, from: Square, unpromotion: bool, moves: &mut UnMoveList) {
-        for to in attacks::pawn_attacks(!self.retro_turn, from) & !self.occupied() {
+    /// Check that the king and the rook, on their castling squares, can go back
+    /// to `king_from` and `rook_from` without anything standing in the way.
+    /// In chess960 the squares before and after castling can overlap.
+    fn can_uncastle(&self, side: CastlingSide, king_from: Square, rook_from: Square) -> bool {
+        let king_to = side.king_to(self.retro_turn);
+        let rook_to = side.rook_to(self.retro_turn);
+        let path = attacks::between(rook_from, rook_to).with(rook_from)
+            | attacks::between(king_from, king_to).with(king_from);
+        (self.occupied() & path & !(Bitboard::from(king_to) | rook_to)).is_empty()
+    }
+
+    fn gen_pawn_uncaptures<S: UnMoveSink>(
+        &self,
+        from: Square,
+        target: Bitboard,
+        unpromotion: bool,
+        moves: &mut S,
+    ) {
+        for to in attacks::pawn_attacks(!self.retro_turn, from) & !self.occupied() & target {
             self.gen_uncaptures(from, to, unpromotion, moves)
         }
     }
 
     // TODO refractor uncapture to uncapture_on, dealing with attacks, unpromotion etc.
-    fn gen_uncaptures(&self, from: Square, to: Square, unpromotion: bool, moves: &mut UnMoveList) {
-        for unmove in self
-            .pockets
-            .color(!self.retro_turn)
-            .clone()
-            .into_iter()
-            .map(|r| {
-                UnMove::new(
-                    from,
-                    to,
-                    if unpromotion {
-                        UnPromotion(Some(r))
-                    } else {
-                        Uncapture(r)
-                    },
-                )
-            })
-        {
+    fn gen_uncaptures<S: UnMoveSink>(
+        &self,
+        from: Square,
+        to: Square,
+        unpromotion: bool,
+        moves: &mut S,
+    ) {
+        for unmove in self.pockets.roles(!self.retro_turn).map(|r| {
+            UnMove::new(
+                from,
+                to,
+                if unpromotion {
+                    UnPromotion(Some(r))
+                } else {
+                    Uncapture(r)
+                },
+            )
+        }) {
             if !(Bitboard::BACKRANKS.contains(unmove.from)
                 && unmove.uncapture() == Some(Role::Pawn))
             {
@@ -478,15 +1567,18 @@ impl PartialEq for RetroBoard {
             && self.board == other.board
             && self.pockets == other.pockets
             && self.ep_square == other.ep_square
+            && self.castling_rights == other.castling_rights
+            && self.castling_mode == other.castling_mode
+            && self.halfmove_clock == other.halfmove_clock
+            && self.fullmoves == other.fullmoves
     }
 }
 
 impl Hash for RetroBoard {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.retro_turn.hash(state);
-        self.board.hash(state);
-        self.pockets.hash(state);
-        self.ep_square.hash(state);
+        self.zobrist.0.hash(state);
+        self.halfmove_clock.hash(state);
+        self.fullmoves.hash(state);
     }
 }
 
@@ -496,9 +1588,9 @@ impl fmt::Debug for RetroBoard {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(&format!(
             "\n{}\nretro_turn = {:?}\n{:?}\nhalfmoves: {:?}\nep square: {:?}\nepd: {}",
-            show_board(&self.board),
+            show_board(&self.board()),
             self.retro_turn,
-            self.pockets,
+            self.pockets(),
             self.halfmoves,
             self.ep_square,
             self.epd()
@@ -507,49 +1599,144 @@ impl fmt::Debug for RetroBoard {
 }
 
 impl fmt::Display for RetroBoard {
+    /// Single line retro fen, which can be parsed back with [`RetroBoard::from_str`].
+    ///
+    /// The board is followed by both pockets in brackets, white uppercase and black lowercase,
+    /// each followed by its unpromotion counter if not 0. The rest is the side to move, castling rights and
+    /// en passant square like in a fen, then the move counters if they are tracked, see [`RetroBoard::new_with_move_counters`].
+    /// It ends with `960` if the castling mode is [`CastlingMode::Chess960`], which cannot always be told from the castling rights.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        <Self as fmt::Debug>::fmt(self, f)
+        let epd = self.epd();
+        let (board, rest) = epd.split_once(' ').expect("Epd has several fields");
+        let pockets = self.pockets();
+        write!(f, "{board}[")?;
+        write_pocket(f, *pockets.color(White), White)?;
+        f.write_str("/")?;
+        write_pocket(f, *pockets.color(Black), Black)?;
+        write!(f, "] {rest}")?;
+        if let Some(fullmoves) = self.fullmoves {
+            write!(f, " {} {fullmoves}", self.halfmove_clock)?;
+        }
+        if self.castling_mode == CastlingMode::Chess960 {
+            f.write_str(" 960")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RetroBoard {
+    type Err = ParseFenError;
+
+    /// Parses the retro fen written by [`RetroBoard`]'s `Display`, the two round-trip exactly.
+    /// The castling mode is [`CastlingMode::Chess960`] if the retro fen ends with `960`,
+    /// otherwise it is detected from the castling rights like in [`RetroBoard::new`].
+    ///
+    /// Pockets are optional, so a fen is also accepted, but must have the case of their color when present.
+    /// Move counters are loaded if present, like in [`RetroBoard::new_with_move_counters`].
+    /// # Examples
+    /// ```
+    /// use retroboard::RetroBoard;
+    /// use shakmaty::Color;
+    ///
+    /// let fen = "q4N2/1p5k/3P1b2/8/6P1/4Q3/3PB1r1/2KR4[PPNBRQ2/pnbrq12] b - - 0 1";
+    /// let r: RetroBoard = fen.parse().unwrap();
+    /// assert_eq!(r.pockets().color(Color::Black).unpromotion, 12);
+    /// assert_eq!(r.to_string(), fen);
+    ///
+    /// let r: RetroBoard = "4k3/8/8/8/8/8/8/4K3 w -".parse().unwrap();
+    /// assert_eq!(r.to_string(), "4k3/8/8/8/8/8/8/4K3[/] w - -");
+    ///
+    /// let r: RetroBoard = "1k1r4/8/8/8/8/8/8/2KR4[/] b - - 960".parse().unwrap();
+    /// assert_eq!(r.castling_mode(), shakmaty::CastlingMode::Chess960);
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (board, rest) = s.split_once(' ').unwrap_or((s, ""));
+        // the marker follows the side to move, castling rights, en passant square and optional move counters
+        let (rest, chess960) = match rest.strip_suffix(" 960") {
+            Some(fields) if matches!(fields.split(' ').count(), 3 | 5) => (fields, true),
+            _ => (rest, false),
+        };
+        let (board, pockets) = match board.split_once('[') {
+            Some((board, pockets)) => (
+                board,
+                pockets
+                    .strip_suffix(']')
+                    .ok_or(ParseFenError::InvalidPocket)?,
+            ),
+            None => (board, "/"),
+        };
+        let (white, black) = pockets
+            .split_once('/')
+            .ok_or(ParseFenError::InvalidPocket)?;
+        let fen = format!("{board} {rest}");
+        let mut rboard = if rest.split(' ').count() > 3 {
+            Self::new_with_move_counters(&fen, "", "")
+        } else {
+            Self::new(fen.trim_end(), "", "")
+        }?;
+        let mut pockets = RetroPockets::default();
+        *pockets.color_mut(White) = parse_pocket(white, White)?;
+        *pockets.color_mut(Black) = parse_pocket(black, Black)?;
+        rboard.pockets = CompactPockets::try_from(&pockets)?;
+        if chess960 {
+            rboard.castling_mode = CastlingMode::Chess960;
+        }
+        rboard.zobrist = rboard.compute_zobrist();
+        Ok(rboard)
     }
 }
 
 impl FromSetup for RetroBoard {
-    /// [`RetroPocket`](crate::RetroPocket) will be empty for both colors
+    /// [`RetroPocket`](crate::RetroPocket) will be empty for both colors.
+    /// Castling rights which do not match the position of the kings and rooks are discarded.
     /// # Warning
     /// No legality check is done, and the behaviour of [`RetroBoard`] is undefined for illegal positions
-    fn from_setup(setup: Setup, _: CastlingMode) -> Result<Self, PositionError<Self>> {
-        Ok(Self {
-            board: setup.board,
+    fn from_setup(setup: Setup, mode: CastlingMode) -> Result<Self, PositionError<Self>> {
+        // chess960 castling rights are discarded in standard mode
+        let castling_rights = Castles::from_setup(&setup, mode)
+            .unwrap_or_else(identity)
+            .castling_rights();
+        let mut rboard = Self {
+            board: CompactBoard::from(&setup.board),
             retro_turn: !setup.turn,
             ep_square: setup.ep_square,
             halfmoves: 0,
-            pockets: RetroPockets::default(),
-        })
+            pockets: CompactPockets::default(),
+            castling_rights: castling_rights.into(),
+            castling_mode: mode,
+            halfmove_clock: 0,
+            fullmoves: None,
+            zobrist: Zobrist64::default(),
+        };
+        rboard.zobrist = rboard.compute_zobrist();
+        Ok(rboard)
     }
 }
 
 impl From<RetroBoard> for Setup {
-    /// [`Setup::halfmoves`] and [`Setup::fullmoves`] are respectively set to 0 and 1
-    /// [`Setup::castling_rights`] is empty
+    /// [`Setup::halfmoves`] is set to [`RetroBoard::halfmove_clock`] and [`Setup::fullmoves`] to [`RetroBoard::fullmoves`]
     fn from(rboard: RetroBoard) -> Self {
+        let fullmoves = rboard.fullmoves();
         Setup {
-            board: rboard.board,
+            board: rboard.board(),
             promoted: Bitboard::EMPTY,
             pockets: None,
             turn: !rboard.retro_turn,
-            castling_rights: Bitboard::EMPTY,
+            castling_rights: rboard.castling_rights(),
             ep_square: rboard.ep_square,
             remaining_checks: None,
-            halfmoves: 0,
-            fullmoves: std::num::NonZeroU32::new(1).unwrap(),
+            halfmoves: rboard.halfmove_clock(),
+            fullmoves,
         }
     }
 }
 
 impl From<RetroBoard> for Chess {
     /// Consider valid positions with too many/impossible checkers (unreachable positions)
-    /// [`Chess::halfmoves`] and [`Chess::fullmoves`] are respectively set to 0 and 1
+    /// [`Chess::halfmoves`] is set to [`RetroBoard::halfmove_clock`] and [`Chess::fullmoves`] to [`RetroBoard::fullmoves`]
     fn from(rboard: RetroBoard) -> Self {
-        Chess::from_setup(Setup::from(rboard), CastlingMode::Standard)
+        let mode = rboard.castling_mode;
+        Chess::from_setup(Setup::from(rboard), mode)
             .or_else(PositionError::ignore_impossible_check)
             .expect("Illegal position")
     }
@@ -557,16 +1744,91 @@ impl From<RetroBoard> for Chess {
 
 impl From<Chess> for RetroBoard {
     /// [`RetroBoard::halfmoves`] is set to 0, and [`RetroPocket`](crate::RetroPocket) will be empty for both colors
+    /// The [`CastlingMode`] of the position is kept.
     fn from(chess: Chess) -> Self {
+        let mode = chess.castles().mode();
         // when converting from a position, the en-passant square should always be set no matter
         // if the capture was possible in the `chess` position, because it tells to the retroboard
         // that the last move was forcily the pawn double pushing
-        Self::from_setup(
-            chess.into_setup(shakmaty::EnPassantMode::Always),
-            CastlingMode::Standard,
-        )
-        .expect("Setup -> RetroBoard should be infaillible")
+        Self::from_setup(chess.into_setup(EnPassantMode::Always), mode)
+            .expect("Setup -> RetroBoard should be infaillible")
+    }
+}
+
+/// Forward move of the unmove, `role` being the one of the piece on its from square before it is pushed.
+fn forward_move(m: &UnMove, role: Role) -> Move {
+    if m.is_uncastling() {
+        return Move::Castle {
+            king: m.from,
+            rook: m.to,
+        };
+    }
+    if m.is_en_passant() {
+        return Move::EnPassant {
+            from: m.to,
+            to: m.from,
+        };
+    }
+    if m.is_unpromotion() {
+        Move::Normal {
+            role: Role::Pawn,
+            from: m.to,
+            capture: m.uncapture(),
+            to: m.from,
+            promotion: Some(role),
+        }
+    } else {
+        Move::Normal {
+            role,
+            from: m.to,
+            capture: m.uncapture(),
+            to: m.from,
+            promotion: None,
+        }
+    }
+}
+
+/// Zobrist value of the pocket count of that role, the king standing for the unpromotion counter.
+#[inline]
+fn pocket_zobrist(color: Color, role: Role, pockets: CompactPockets) -> Zobrist64 {
+    let pieces = if role == Role::King {
+        pockets.unpromotion(color)
+    } else {
+        pockets.get(color, role)
+    };
+    Zobrist64::zobrist_for_pocket(color, role, pieces)
+}
+
+/// Pieces of the pocket with the case of `color`, followed by the unpromotion counter if not 0.
+fn write_pocket(f: &mut fmt::Formatter<'_>, pocket: RetroPocket, color: Color) -> fmt::Result {
+    for role in &Role::ALL[..5] {
+        for _ in 0..pocket.get(*role) {
+            write!(f, "{}", role.of(color).char())?;
+        }
+    }
+    if pocket.unpromotion > 0 {
+        write!(f, "{}", pocket.unpromotion)?;
     }
+    Ok(())
+}
+
+/// Reverse of `write_pocket`, unlike [`RetroPocket::from_str`] the pieces must have the case of `color`
+/// and the unpromotion counter can have several digits.
+fn parse_pocket(s: &str, color: Color) -> Result<RetroPocket, ParseFenError> {
+    let (pieces, unpromotion) = s.split_at(s.find(|c: char| c.is_ascii_digit()).unwrap_or(s.len()));
+    if pieces
+        .chars()
+        .any(|c| c.is_ascii_uppercase() != color.is_white())
+    {
+        return Err(ParseFenError::InvalidPocket);
+    }
+    let mut pocket = RetroPocket::from_str(pieces)?;
+    if !unpromotion.is_empty() {
+        pocket.unpromotion = unpromotion
+            .parse()
+            .map_err(|_| ParseFenError::InvalidPocket)?;
+    }
+    Ok(pocket)
 }
 
 #[inline]
@@ -588,6 +1850,42 @@ fn unicode(c: char) -> char {
     }
 }
 
+/// Parse the castling part of a fen, following shakmaty parsing, and check the rights are consistent with the board.
+fn parse_castling_rights(
+    board: &Board,
+    castling: &str,
+) -> Result<(Bitboard, CastlingMode), ParseFenError> {
+    let mut setup = Setup::empty();
+    setup.board = board.clone();
+    if castling != "-" {
+        for c in castling.chars() {
+            let color = Color::from_white(c.is_ascii_uppercase());
+            let rooks_and_kings =
+                board.by_color(color) & (board.rooks() | board.kings()) & color.backrank();
+            setup.castling_rights.add(match c.to_ascii_lowercase() {
+                'k' => rooks_and_kings
+                    .last()
+                    .filter(|sq| board.rooks().contains(*sq))
+                    .unwrap_or_else(|| Square::from_coords(File::H, color.backrank())),
+                'q' => rooks_and_kings
+                    .first()
+                    .filter(|sq| board.rooks().contains(*sq))
+                    .unwrap_or_else(|| Square::from_coords(File::A, color.backrank())),
+                file => Square::from_coords(
+                    File::from_char(file).ok_or(ParseFenError::InvalidCastling)?,
+                    color.backrank(),
+                ),
+            });
+        }
+    }
+    let mode = CastlingMode::detect(&setup);
+    // like `FromSetup`, castling rights which do not match the kings and rooks are discarded
+    let castling_rights = Castles::from_setup(&setup, mode)
+        .unwrap_or_else(identity)
+        .castling_rights();
+    Ok((castling_rights, mode))
+}
+
 #[inline]
 fn retro_attacks(from: Square, p: Piece, occupied: Bitboard) -> Bitboard {
     match p {
@@ -633,23 +1931,410 @@ fn closest_and_further_square(bb: Bitboard, of: Square, steppers: Bitboard) -> (
 /// that high depths are feasible at all.
 #[must_use]
 pub fn perft(r: &RetroBoard, depth: u32) -> u64 {
+    perft_push_pop(&mut r.clone(), depth)
+}
+
+/// Same as [`perft`], but the unmoves of the root position are split across threads.
+/// # Examples
+/// ```
+/// use retroboard::{par_perft, perft, RetroBoard};
+/// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+/// assert_eq!(par_perft(&r, 2), perft(&r, 2));
+/// ```
+#[cfg(feature = "rayon")]
+#[must_use]
+pub fn par_perft(r: &RetroBoard, depth: u32) -> u64 {
+    use rayon::prelude::*;
+
+    if depth < 2 {
+        return perft(r, depth);
+    }
+    r.legal_unmoves()
+        .par_iter()
+        .map(|m| {
+            let mut r2 = *r;
+            r2.push(m);
+            perft_push_pop(&mut r2, depth - 1)
+        })
+        .sum()
+}
+
+/// Number of leaves under each unmove of the root position, see [`perft`].
+/// # Examples
+/// ```
+/// use retroboard::{perft, perft_divide, RetroBoard};
+/// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+/// let divide = perft_divide(&r, 2);
+/// assert_eq!(divide.len(), 24);
+/// assert_eq!(divide.iter().map(|(_, nodes)| nodes).sum::<u64>(), perft(&r, 2));
+/// ```
+#[must_use]
+pub fn perft_divide(r: &RetroBoard, depth: u32) -> Vec<(UnMove, u64)> {
+    if depth < 1 {
+        return Vec::new();
+    }
+    let mut r = *r;
+    let moves = r.legal_unmoves();
+    moves
+        .into_iter()
+        .map(|m| {
+            let undo = r.push(&m);
+            let nodes = perft_push_pop(&mut r, depth - 1);
+            r.pop(&m, undo);
+            (m, nodes)
+        })
+        .collect()
+}
+
+/// Leaves of [`perft_stats`], broken down by the [`MoveKind`](crate::MoveKind) of the unmove leading to them.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct PerftStats {
+    /// Total number of leaves, same as [`perft`].
+    pub leaves: u64,
+    pub normal: u64,
+    /// Uncaptures by role of the uncaptured piece, unpromotions and en passant excluded.
+    pub uncaptures: ByRole<u64>,
+    /// Unpromotions, with or without uncapture.
+    pub unpromotions: u64,
+    pub en_passant: u64,
+    pub uncastling: u64,
+    /// Leaves where the opponent of the side to retract is in check.
+    pub checks: u64,
+}
+
+impl PerftStats {
+    fn add_leaf(&mut self, m: &UnMove, check: bool) {
+        self.leaves += 1;
+        if m.is_uncastling() {
+            self.uncastling += 1;
+        } else if m.is_en_passant() {
+            self.en_passant += 1;
+        } else if m.is_unpromotion() {
+            self.unpromotions += 1;
+        } else if let Some(role) = m.uncapture() {
+            *self.uncaptures.get_mut(role) += 1;
+        } else {
+            self.normal += 1;
+        }
+        if check {
+            self.checks += 1;
+        }
+    }
+}
+
+/// Same as [`perft`], with the leaves broken down in [`PerftStats`].
+/// # Examples
+/// ```
+/// use retroboard::{perft, perft_stats, RetroBoard};
+/// let r = RetroBoard::new("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "", "NP").unwrap();
+/// let stats = perft_stats(&r, 1);
+/// assert_eq!(stats.leaves, perft(&r, 1));
+/// assert_eq!(stats.normal, 1);
+/// assert_eq!(stats.checks, 0);
+/// ```
+#[must_use]
+pub fn perft_stats(r: &RetroBoard, depth: u32) -> PerftStats {
+    let mut stats = PerftStats::default();
     if depth < 1 {
-        1
+        stats.leaves = 1;
     } else {
-        let moves = r.legal_unmoves();
+        perft_stats_push_pop(&mut r.clone(), depth, &mut stats);
+    }
+    stats
+}
 
+fn perft_stats_push_pop(r: &mut RetroBoard, depth: u32, stats: &mut PerftStats) {
+    for m in r.legal_unmoves() {
+        let undo = r.push(&m);
         if depth == 1 {
-            moves.len() as u64
+            stats.add_leaf(&m, r.checkers(!r.retro_turn).any());
         } else {
-            moves
-                .iter()
-                .map(|m| {
-                    let mut child = r.clone();
-                    child.push(m);
-                    perft(&child, depth - 1)
-                })
-                .sum()
+            perft_stats_push_pop(r, depth - 1, stats);
+        }
+        r.pop(&m, undo);
+    }
+}
+
+/// Same as [`perft`], but the number of leaves of the positions already visited are kept in a
+/// table of `entries` slots, indexed by [`RetroBoard::zobrist_hash`] and depth.
+/// Retro trees transpose a lot since quiet unmoves commute, so this is much faster at high depths.
+///
+/// Hash collisions are not detected, but are very unlikely with 64-bit keys.
+/// # Panics
+/// Panics if `entries` is 0.
+/// # Examples
+/// ```
+/// use retroboard::{hashed_perft, perft, RetroBoard};
+/// let r = RetroBoard::new("q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1", "2PNBRQ", "3NBRQP").unwrap();
+/// assert_eq!(hashed_perft(&r, 3, 1 << 16), perft(&r, 3));
+/// ```
+#[must_use]
+pub fn hashed_perft(r: &RetroBoard, depth: u32, entries: usize) -> u64 {
+    assert!(entries > 0, "The perft table needs at least one entry");
+    let mut table = vec![PerftEntry::default(); entries];
+    hashed_perft_push_pop(&mut r.clone(), depth, &mut table)
+}
+
+#[derive(Clone, Copy, Default)]
+struct PerftEntry {
+    key: u64,
+    depth: u32, // 0 for empty entries, since they are never stored
+    nodes: u64,
+}
+
+fn hashed_perft_push_pop(r: &mut RetroBoard, depth: u32, table: &mut [PerftEntry]) -> u64 {
+    if depth < 1 {
+        return 1;
+    }
+    // the move counters are not part of the key, so they must not constrain the subtree
+    let cacheable = r.halfmove_clock == 0
+        && r.fullmoves
+            .map_or(true, |fullmoves| u32::from(fullmoves.get()) > depth);
+    let key = r.zobrist.0;
+    #[allow(clippy::cast_possible_truncation)]
+    let index = (key.wrapping_add(u64::from(depth)) % table.len() as u64) as usize;
+    if cacheable && table[index].key == key && table[index].depth == depth {
+        return table[index].nodes;
+    }
+    let nodes = if depth == 1 {
+        r.count_legal_unmoves() as u64
+    } else {
+        r.legal_unmoves()
+            .iter()
+            .map(|m| {
+                let undo = r.push(m);
+                let nodes = hashed_perft_push_pop(r, depth - 1, table);
+                r.pop(m, undo);
+                nodes
+            })
+            .sum()
+    };
+    if cacheable {
+        table[index] = PerftEntry { key, depth, nodes };
+    }
+    nodes
+}
+
+/// Check and pin information about the king of the side not to retro-move, see [`RetroBoard::retro_context`].
+///
+/// It only depends on the position, so it can be computed once and reused for several queries on it.
+/// Using it with another position gives meaningless results.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct RetroContext {
+    king: Square,
+    checkers: Bitboard,
+    blockers: Bitboard,
+    evasions: Bitboard,
+}
+
+impl RetroContext {
+    /// Square of the king of the side not to retro-move.
+    #[inline]
+    #[must_use]
+    pub fn king(&self) -> Square {
+        self.king
+    }
+
+    /// Our pieces giving check to that king.
+    #[inline]
+    #[must_use]
+    pub fn checkers(&self) -> Bitboard {
+        self.checkers
+    }
+
+    /// Our pieces which are the only one between that king and one of our sliders.
+    #[inline]
+    #[must_use]
+    pub fn blockers(&self) -> Bitboard {
+        self.blockers
+    }
+
+    /// Squares a piece other than a checker must go back to, to stop the check.
+    /// Full if the king is not in check, empty if it is checked by a stepper or several pieces.
+    #[inline]
+    #[must_use]
+    pub fn evasions(&self) -> Bitboard {
+        self.evasions
+    }
+}
+
+/// Generation stages of [`LegalUnMoves`], in order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Stage {
+    Pieces,
+    Uncaptures,
+    UnPromotions,
+    Pawns,
+    EnPassant,
+    UnCastling,
+    Done,
+}
+
+impl Stage {
+    /// Generation of each stage is restricted with a filter, which also handles the ep square.
+    fn filter(self) -> UnMoveFilter {
+        let all = UnMoveFilter::ALL.roles;
+        let pieces = ByRole { pawn: false, ..all };
+        let pawns = ByRole {
+            pawn: true,
+            ..ByRole::default()
+        };
+        let (roles, kinds) = match self {
+            Self::Pieces => (pieces, MoveKindMask::QUIET),
+            Self::Uncaptures => (pieces, MoveKindMask::UNCAPTURE),
+            Self::UnPromotions => (all, MoveKindMask::UNPROMOTION),
+            Self::Pawns => (pawns, MoveKindMask::QUIET | MoveKindMask::UNCAPTURE),
+            Self::EnPassant => (all, MoveKindMask::EN_PASSANT),
+            Self::UnCastling => (all, MoveKindMask::UNCASTLING),
+            Self::Done => (all, MoveKindMask::NONE),
+        };
+        UnMoveFilter {
+            roles,
+            kinds,
+            ..UnMoveFilter::ALL
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::Pieces => Self::Uncaptures,
+            Self::Uncaptures => Self::UnPromotions,
+            Self::UnPromotions => Self::Pawns,
+            Self::Pawns => Self::EnPassant,
+            Self::EnPassant => Self::UnCastling,
+            Self::UnCastling | Self::Done => Self::Done,
+        }
+    }
+}
+
+/// Iterator over the legal unmoves of a [`RetroBoard`], see [`RetroBoard::legal_unmoves_iter`].
+#[derive(Clone, Debug)]
+pub struct LegalUnMoves<'a> {
+    rboard: &'a RetroBoard,
+    stage: Stage,      // next stage to generate
+    moves: UnMoveList, // unmoves of the current stage
+    index: usize,
+    ctx: RetroContext,
+}
+
+impl Iterator for LegalUnMoves<'_> {
+    type Item = UnMove;
+
+    fn next(&mut self) -> Option<UnMove> {
+        loop {
+            if let Some(m) = self.moves.get(self.index) {
+                self.index += 1;
+                return Some(m.clone());
+            }
+            if self.stage == Stage::Done {
+                return None;
+            }
+            self.moves.clear();
+            self.index = 0;
+            self.rboard
+                .gen_stage(self.stage, &self.ctx, &mut self.moves);
+            self.stage = self.stage.next();
+        }
+    }
+}
+
+impl FusedIterator for LegalUnMoves<'_> {}
+
+/// Error when more distinct positions than the limit are found at some depth, see [`unique_predecessors`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PositionLimitExceeded {
+    /// Depth at which the limit has been exceeded.
+    pub depth: u32,
+    /// Number of distinct positions at each depth before it, starting with the root position at depth 0.
+    pub counts: Vec<usize>,
+}
+
+impl fmt::Display for PositionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "too many distinct positions at depth {}, counts before: {:?}",
+            self.depth, self.counts
+        )
+    }
+}
+
+impl Error for PositionLimitExceeded {}
+
+/// Distinct positions reached by retracting exactly `depth` plies, walking [`RetroBoard::legal_unmoves`] breadth-first.
+/// Positions are deduplicated using the [`Eq`] and [`Hash`] implementations of [`RetroBoard`].
+/// If `limit` is set and more than `limit` distinct positions are found at some depth,
+/// the search stops and [`PositionLimitExceeded`] is returned, no position is dropped silently.
+/// # Examples
+/// ```
+/// use retroboard::{unique_predecessors, RetroBoard};
+/// let r = RetroBoard::new_no_pockets("8/8/8/8/5k2/8/3K4/8 b - - 0 1").unwrap();
+/// assert_eq!(unique_predecessors(&r, 2, None).unwrap().len(), 52);
+/// assert_eq!(unique_predecessors(&r, 2, Some(52)).unwrap().len(), 52);
+/// assert!(unique_predecessors(&r, 2, Some(50)).is_err());
+/// ```
+pub fn unique_predecessors(
+    r: &RetroBoard,
+    depth: u32,
+    limit: Option<usize>,
+) -> Result<HashSet<RetroBoard>, PositionLimitExceeded> {
+    predecessors_by_depth(r, depth, limit).map(|(positions, _)| positions)
+}
+
+/// Number of distinct positions reached by retracting 0 to `depth` plies, see [`unique_predecessors`].
+/// # Examples
+/// ```
+/// use retroboard::{count_unique_predecessors, RetroBoard};
+/// let r = RetroBoard::new_no_pockets("8/8/8/8/5k2/8/3K4/8 b - - 0 1").unwrap();
+/// assert_eq!(count_unique_predecessors(&r, 2, None).unwrap(), vec![1, 7, 52]);
+/// ```
+pub fn count_unique_predecessors(
+    r: &RetroBoard,
+    depth: u32,
+    limit: Option<usize>,
+) -> Result<Vec<usize>, PositionLimitExceeded> {
+    predecessors_by_depth(r, depth, limit).map(|(_, counts)| counts)
+}
+
+fn predecessors_by_depth(
+    r: &RetroBoard,
+    depth: u32,
+    limit: Option<usize>,
+) -> Result<(HashSet<RetroBoard>, Vec<usize>), PositionLimitExceeded> {
+    let mut positions = HashSet::from([*r]);
+    let mut counts = vec![1];
+    for d in 1..=depth {
+        let mut predecessors = HashSet::new();
+        for pos in &positions {
+            for m in pos.legal_unmoves() {
+                let mut predecessor = *pos;
+                predecessor.push(&m);
+                predecessors.insert(predecessor);
+                if limit.map_or(false, |limit| predecessors.len() > limit) {
+                    return Err(PositionLimitExceeded { depth: d, counts });
+                }
+            }
         }
+        counts.push(predecessors.len());
+        positions = predecessors;
+    }
+    Ok((positions, counts))
+}
+
+fn perft_push_pop(r: &mut RetroBoard, depth: u32) -> u64 {
+    match depth {
+        0 => 1,
+        1 => r.count_legal_unmoves() as u64,
+        _ => r
+            .legal_unmoves()
+            .iter()
+            .map(|m| {
+                let undo = r.push(m);
+                let nodes = perft_push_pop(r, depth - 1);
+                r.pop(m, undo);
+                nodes
+            })
+            .sum(),
     }
 }
 
@@ -659,9 +2344,10 @@ mod tests {
 
     use indoc::indoc;
     use paste::paste;
-    use shakmaty::{fen::Fen, uci::Uci, Position};
+    use shakmaty::{fen::Fen, uci::Uci, zobrist::ZobristHash, Move, Position};
 
     use super::*;
+    use crate::PackedUnMoveList;
 
     fn u(s: &str) -> UnMove {
         UnMove::from_retro_uci(s).unwrap()
@@ -699,57 +2385,298 @@ mod tests {
     }
 
     #[test]
-    fn test_new_no_pockets() {
-        let r =
-            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
-                .expect("Retroboard because fen is legal");
-        assert_eq!(r.retro_turn, Black);
+    fn test_compact_representation() {
+        fn is_copy<T: Copy>() {}
+        is_copy::<RetroBoard>();
+        assert!(std::mem::size_of::<RetroBoard>() <= 80);
+        assert_eq!(
+            RetroBoard::new_no_pockets("8/8/8/8/8/8/8/4K2k w - - 0 1")
+                .unwrap()
+                .pockets(),
+            RetroPockets::default()
+        );
+        assert!(RetroBoard::new("4k3/8/8/8/8/8/8/4K3 w - - 0 1", &"P".repeat(16), "").is_err());
+        let r = RetroBoard::new_with_move_counters("4k3/8/8/8/8/8/8/4K3 w - - 65535 65535", "", "")
+            .unwrap();
+        assert_eq!(r.halfmove_clock(), 65535);
+        assert_eq!(r.fullmoves().get(), 65535);
+        assert_eq!(
+            RetroBoard::new_with_move_counters("4k3/8/8/8/8/8/8/4K3 w - - 65536 1", "", ""),
+            Err(ParseFenError::InvalidHalfmoveClock)
+        );
+        assert_eq!(
+            RetroBoard::new_with_move_counters("4k3/8/8/8/8/8/8/4K3 w - - 0 65536", "", ""),
+            Err(ParseFenError::InvalidFullmoves)
+        );
+    }
+
+    #[test]
+    fn test_new_no_pockets() {
+        let r =
+            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+                .expect("Retroboard because fen is legal");
+        assert_eq!(r.retro_turn, Black);
+        assert_eq!(
+            r.board(),
+            Board::from_ascii_board_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR".as_bytes())
+                .unwrap()
+        );
+        assert_eq!(r.pockets(), RetroPockets::default());
+        assert_eq!(r.halfmoves, 0);
+    }
+
+    #[test]
+    fn test_from_setup() {
+        let r =
+            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+                .expect("Retroboard because fen is legal");
+        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let r_setup = RetroBoard::from_setup(setup, CastlingMode::Standard).unwrap();
+        assert_eq!(r, r_setup);
+    }
+
+    #[test]
+    fn test_from_chess() {
+        let r =
+            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+                .expect("Retroboard because fen is legal");
+        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let chess: Chess = setup.position(CastlingMode::Standard).unwrap();
+        assert_eq!(r, RetroBoard::from(chess));
+    }
+
+    #[test]
+    fn test_to_chess() {
+        let r =
+            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+                .expect("Retroboard because fen is legal");
+        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let chess: Chess = setup.position(CastlingMode::Standard).unwrap();
+        assert_eq!(Chess::from(r), chess);
+    }
+
+    #[test]
+    fn test_new_castling_rights() {
+        let r = RetroBoard::new_no_pockets("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(r.castling_rights(), Bitboard::CORNERS);
+        let r = RetroBoard::new_no_pockets("r3k2r/8/8/8/8/8/8/R3K2R w Hq - 0 1").unwrap();
+        assert_eq!(r.castling_rights(), Bitboard::from(Square::H1) | Square::A8);
+        let r = RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/4K3 w K - 0 1").unwrap();
+        assert_eq!(r.castling_rights(), Bitboard::EMPTY);
+        let r = RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/K6R w K - 0 1").unwrap();
+        assert_eq!(r.castling_rights(), Bitboard::EMPTY);
+        // same policy as `FromSetup`
+        let r = RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/R3K3 w KQ - 0 1").unwrap();
+        assert_eq!(r.castling_rights(), Bitboard::from(Square::A1));
+        assert_eq!(
+            RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/4K3 w X - 0 1"),
+            Err(ParseFenError::InvalidCastling)
+        );
+    }
+
+    #[test]
+    fn test_from_setup_invalid_castling_rights() {
+        let setup: Setup = "4k3/8/8/8/8/8/8/R3K1R1 w KQ - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let r = RetroBoard::from_setup(setup, CastlingMode::Standard).unwrap();
+        assert_eq!(r.castling_rights(), Bitboard::from(Square::A1));
+    }
+
+    #[test]
+    fn test_new_with_move_counters() {
+        let fen = "4k3/8/8/8/8/8/8/4K3 w - - 12 1";
+        assert_eq!(
+            RetroBoard::new_with_move_counters(fen, "", "")
+                .unwrap()
+                .halfmove_clock(),
+            12
+        );
+        assert_eq!(RetroBoard::new_no_pockets(fen).unwrap().halfmove_clock(), 0);
+        // a clock of 0 does not force the first unmove to be a breaking one
+        let r = RetroBoard::new_with_move_counters("4k3/8/8/8/8/3P4/8/4K3 b - - 0 10", "", "Q")
+            .unwrap();
+        let moves = r.legal_unmoves();
+        assert!(moves.contains(&u("e1e2")));
+        assert!(moves.contains(&u("Qd3c2")));
+        assert!(moves.contains(&u("d3d2")));
+        assert_eq!(
+            RetroBoard::new_with_move_counters("4k3/8/8/8/8/8/8/4K3 w - - x 1", "", ""),
+            Err(ParseFenError::InvalidHalfmoveClock)
+        );
+    }
+
+    #[test]
+    fn test_retro_fen() {
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K3[/] w - -",
+            "4k3/8/8/8/8/8/8/4K3[/] b - - 12 40",
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR[/] w KQkq e6 0 2",
+            "r3k2r/8/8/8/8/8/8/R3K2R[PPNN1/bq] w Kq -",
+            "1r2k1r1/8/8/8/8/8/8/1R2K1R1[/p15] w Kq - 960",
+            "1k1r4/8/8/8/8/8/8/2KR4[/] b - - 960",
+            "1k1r4/8/8/8/8/8/8/2KR4[Q/] b - - 0 960 960",
+            "q4N2/1p5k/3P1b2/8/6P1/4Q3/3PB1r1/2KR4[PPNBRQ2/ppnbrq3] b - - 0 1",
+        ] {
+            let r = RetroBoard::from_str(fen).unwrap();
+            assert_eq!(r.to_string(), fen);
+            assert_eq!(RetroBoard::from_str(&r.to_string()).unwrap(), r);
+        }
         assert_eq!(
-            r.board,
-            Board::from_ascii_board_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR".as_bytes())
+            RetroBoard::from_str("1r2k1r1/8/8/8/8/8/8/1R2K1R1[/p15] w Gb -")
                 .unwrap()
+                .castling_mode(),
+            CastlingMode::Chess960
         );
-        assert_eq!(r.pockets, RetroPockets::default());
-        assert_eq!(r.halfmoves, 0);
+        let r = RetroBoard::from_setup(
+            Setup::from(RetroBoard::new_no_pockets("1k1r4/8/8/8/8/8/8/2KR4 b - -").unwrap()),
+            CastlingMode::Chess960,
+        )
+        .unwrap();
+        let parsed = RetroBoard::from_str(&r.to_string()).unwrap();
+        assert_eq!(parsed.castling_mode(), CastlingMode::Chess960);
+        assert_eq!(parsed, r);
+        assert_eq!(parsed.legal_unmoves(), r.legal_unmoves());
+        let mut r = RetroBoard::new_with_move_counters(
+            "q4N2/1p5k/3P1b2/8/6P1/4Q3/3PB1r1/2KR4 b - - 2 30",
+            "PPNBRQ2",
+            "NBRQP3",
+        )
+        .unwrap();
+        for i in 0..6 {
+            let moves = r.legal_unmoves();
+            r.push(&moves[i * 7 % moves.len()]);
+            let parsed = RetroBoard::from_str(&r.to_string()).unwrap();
+            assert_eq!(parsed, r);
+            assert_eq!(parsed.zobrist_hash(), r.zobrist_hash());
+        }
+        assert_eq!(
+            RetroBoard::from_str("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap(),
+            RetroBoard::new_with_move_counters("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "", "").unwrap()
+        );
+        for invalid in [
+            "4k3/8/8/8/8/8/8/4K3[P] w - -",
+            "4k3/8/8/8/8/8/8/4K3[P/ w - -",
+            "4k3/8/8/8/8/8/8/4K3[X/] w - -",
+            "4k3/8/8/8/8/8/8/4K3[2P/] w - -",
+            "4k3/8/8/8/8/8/8/4K3[16/] w - -",
+            "4k3/8/8/8/8/8/8/4K3[p/P] w - -",
+            "4k3/8/8/8/8/8/8/4K3[PQp/] w - -",
+            "4k3/8/8/8/8/8/8/4K3[/qR2] w - -",
+        ] {
+            assert_eq!(
+                RetroBoard::from_str(invalid),
+                Err(ParseFenError::InvalidPocket)
+            );
+        }
     }
 
     #[test]
-    fn test_from_setup() {
-        let r =
-            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
-                .expect("Retroboard because fen is legal");
-        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
-            .parse::<Fen>()
-            .unwrap()
-            .into_setup();
-        let r_setup = RetroBoard::from_setup(setup, CastlingMode::Standard).unwrap();
-        assert_eq!(r, r_setup);
+    fn test_round_trip() {
+        let r = RetroBoard::new("4k3/8/8/8/4P3/8/8/4K3 b - - 0 1", "", "").unwrap();
+        let unmove = UnMove::from_retro_uci("e4e2").unwrap();
+        let mut after = r;
+        after.push(&unmove);
+        assert_eq!(r.round_trip(&after, &r.forward_move(&unmove)), Ok(()));
+        let single_push = Move::Normal {
+            role: Role::Pawn,
+            from: Square::E2,
+            capture: None,
+            to: Square::E3,
+            promotion: None,
+        };
+        assert_eq!(
+            r.round_trip(&after, &single_push),
+            Err(InconsistencyKind::DifferentPosition)
+        );
+        let king_move = Move::Normal {
+            role: Role::King,
+            from: Square::E1,
+            capture: None,
+            to: Square::E2,
+            promotion: None,
+        };
+        assert_eq!(
+            r.round_trip(&after, &king_move),
+            Err(InconsistencyKind::IllegalMove)
+        );
+        assert!(r.check_consistency().is_ok());
     }
 
     #[test]
-    fn test_from_chess() {
-        let r =
-            RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
-                .expect("Retroboard because fen is legal");
-        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
-            .parse::<Fen>()
-            .unwrap()
-            .into_setup();
-        let chess: Chess = setup.position(CastlingMode::Standard).unwrap();
-        assert_eq!(r, RetroBoard::from(chess));
+    fn test_fullmoves() {
+        let mut r = RetroBoard::new_with_move_counters(
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+            "",
+            "",
+        )
+        .unwrap();
+        assert_eq!(r.fullmoves().get(), 2);
+        r.push(&u("e5e7"));
+        assert_eq!(r.fullmoves().get(), 1);
+        assert!(r.legal_unmoves().contains(&u("e4e2")));
+        r.push(&u("e4e2"));
+        assert_eq!(r.fullmoves().get(), 1);
+        assert!(r.legal_unmoves().is_empty());
+        assert_eq!(Chess::from(r), Chess::default());
+        assert_eq!(
+            RetroBoard::new_with_move_counters("4k3/8/8/8/8/8/8/4K3 w - - 0 0", "", ""),
+            Err(ParseFenError::InvalidFullmoves)
+        );
     }
 
     #[test]
-    fn test_to_chess() {
-        // castling rights are lost
-        let r = RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1")
-            .expect("Retroboard because fen is legal");
-        let setup: Setup = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1"
+    fn test_fullmoves_to_chess() {
+        let fen = "4k3/8/8/8/8/8/8/4K3 b - - 3 40";
+        let r = RetroBoard::new_with_move_counters(fen, "", "").unwrap();
+        let chess: Chess = fen
             .parse::<Fen>()
             .unwrap()
-            .into_setup();
-        let chess: Chess = setup.position(CastlingMode::Standard).unwrap();
+            .into_position(CastlingMode::Standard)
+            .unwrap();
         assert_eq!(Chess::from(r), chess);
+        assert_eq!(Chess::from(r).fullmoves().get(), 40);
+    }
+
+    #[test]
+    fn test_halfmove_clock_constraint() {
+        let fen = "4k3/4p3/8/8/4P3/8/8/R3K1N1 b - - 2 10";
+        let r = RetroBoard::new(fen, "", "PNQ").unwrap();
+        let mut r_clock = RetroBoard::new_with_move_counters(fen, "", "PNQ").unwrap();
+        let mut quiet: Vec<UnMove> = r
+            .legal_unmoves()
+            .into_iter()
+            .filter(|m| m.uncapture().is_none() && r.board.role_at(m.from) != Some(Role::Pawn))
+            .collect();
+        assert!(quiet.len() < r.legal_unmoves().len());
+        let mut unmoves = r_clock.legal_unmoves().to_vec();
+        quiet.sort_by_key(UnMove::to_retro_uci);
+        unmoves.sort_by_key(UnMove::to_retro_uci);
+        assert_eq!(quiet, unmoves);
+        r_clock.push(&u("a1a3"));
+        assert_eq!(r_clock.halfmove_clock(), 1);
+        r_clock.push(&u("e8d8"));
+        assert_eq!(r_clock.halfmove_clock(), 0);
+        assert!(r_clock.legal_unmoves().contains(&u("e4e3")));
+        assert_eq!(Chess::from(r_clock).halfmoves(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "breaking unmove while the halfmove clock is running")]
+    fn test_halfmove_clock_push_breaking() {
+        let mut r =
+            RetroBoard::new_with_move_counters("4k3/8/8/8/4P3/8/8/4K3 b - - 1 1", "", "").unwrap();
+        r.push(&u("e4e3"));
     }
 
     #[test]
@@ -762,7 +2689,7 @@ mod tests {
         r.push(&u("f3g1"));
         r.push(&u("f6g8"));
         let mut hashset: HashSet<RetroBoard> = HashSet::new();
-        hashset.insert(r.clone());
+        hashset.insert(r);
         let r2 =
             RetroBoard::new_no_pockets("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1")
                 .unwrap();
@@ -771,6 +2698,33 @@ mod tests {
         assert!(hashset.contains(&r2))
     }
 
+    #[test]
+    fn test_zobrist_hash() {
+        let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1";
+        let r = RetroBoard::new(fen, "", "").unwrap();
+        let pos: Chess = r.into();
+        assert_eq!(r.zobrist_hash(), pos.zobrist_hash(EnPassantMode::Legal));
+        let mut keys = HashSet::new();
+        for (fen, pocket_white, pocket_black) in [
+            (fen, "", ""),
+            ("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq - 0 1", "", ""),
+            ("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQk d6 0 1", "", ""),
+            ("r3k2r/8/8/3pP3/8/8/8/R3K2R b KQkq - 0 1", "", ""),
+            (fen, "P", ""),
+            (fen, "", "P"),
+            (fen, "1", ""),
+            (fen, "2", ""),
+            (fen, "", "1"),
+        ] {
+            assert!(keys.insert(
+                RetroBoard::new(fen, pocket_white, pocket_black)
+                    .unwrap()
+                    .zobrist_hash()
+                    .0
+            ));
+        }
+    }
+
     #[test]
     fn test_retro_attacks() {
         assert_eq!(
@@ -826,7 +2780,7 @@ mod tests {
         let rboard = RetroBoard::new_no_pockets("1qrb4/1k2n3/1P2p3/1N1K4/1BQ5/1R1R4/1Q2B3/1K3N2")
             .expect("valid fen");
         let compare_trans = |trans: &dyn Fn(&mut RetroBoard), fen: &str| {
-            let mut rboard_trans = rboard.clone();
+            let mut rboard_trans = rboard;
             trans(&mut rboard_trans);
             assert_eq!(
                 rboard_trans,
@@ -863,6 +2817,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rboard_transformation_castling_rights_and_ep_square() {
+        let rboard =
+            RetroBoard::new_no_pockets("r3k3/8/8/3pP3/8/8/8/4K2R w Kq d6 0 1").expect("valid fen");
+        let mut flipped = rboard;
+        flipped.flip_horizontal();
+        let expected = RetroBoard::new_no_pockets("3k3r/8/8/3Pp3/8/8/8/R2K4 w - e6 0 1").unwrap();
+        assert_eq!(flipped, expected);
+        assert_eq!(flipped.zobrist_hash(), expected.zobrist_hash());
+        let mut flipped = rboard;
+        flipped.flip_vertical();
+        let expected = RetroBoard::new_no_pockets("4K2R/8/8/8/3pP3/8/8/r3k3 w - - 0 1").unwrap();
+        assert_eq!(flipped, expected);
+        assert_eq!(flipped.zobrist_hash(), expected.zobrist_hash());
+        let mut rotated = rboard;
+        rotated.rotate_180();
+        let expected = RetroBoard::new_no_pockets("R2K4/8/8/8/3Pp3/8/8/3k3r w - - 0 1").unwrap();
+        assert_eq!(rotated, expected);
+        assert_eq!(rotated.zobrist_hash(), expected.zobrist_hash());
+    }
+
     #[test]
     fn test_push_uncapture() {
         for piece in "PNBRQ".chars() {
@@ -877,6 +2852,243 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_try_push() {
+        let fen = "1R6/7k/8/8/8/8/8/1K6 b - - 0 1";
+        let r = RetroBoard::new(fen, "1", "").unwrap();
+        for (illegal, reason) in [
+            ("a1a2", IllegalUnMove::EmptyFromSquare),
+            ("b8c6", IllegalUnMove::CannotReach),
+            ("Nb8b7", IllegalUnMove::EmptyPocket),
+            ("b8h8", IllegalUnMove::GivesCheck),
+        ] {
+            let mut r2 = r;
+            assert!(!r.is_legal_unmove(&u(illegal)));
+            assert_eq!(r2.try_push(&u(illegal)), Err(reason));
+            assert_eq!(r2, r);
+        }
+        let no_unpromotion = RetroBoard::new_no_pockets(fen).unwrap();
+        assert!(!no_unpromotion.is_legal_unmove(&u("Ub8b7")));
+        let mut r2 = r;
+        assert!(r2.try_push(&u("Ub8b7")).is_ok());
+        let mut r3 = r;
+        r3.push(&u("Ub8b7"));
+        assert_eq!(r2, r3);
+    }
+
+    #[test]
+    fn test_check_unmove() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        for (r, unmove, expected) in [
+            (
+                RetroBoard::new_with_move_counters(start, "", "").unwrap(),
+                "g8f6",
+                Err(IllegalUnMove::StartOfGame),
+            ),
+            (
+                RetroBoard::new_no_pockets("r3k3/8/8/8/8/8/8/4K3 w q - 0 1").unwrap(),
+                "a8a7",
+                Err(IllegalUnMove::CastlingRights),
+            ),
+            (
+                RetroBoard::new_no_pockets("r3k3/8/8/8/8/8/8/4K3 w q - 0 1").unwrap(),
+                "e8f8",
+                Err(IllegalUnMove::CastlingRights),
+            ),
+            (
+                RetroBoard::new_with_move_counters("4k3/8/8/8/8/4P3/8/4K3 b - - 3 10", "", "")
+                    .unwrap(),
+                "e3e2",
+                Err(IllegalUnMove::HalfmoveClock),
+            ),
+            (
+                RetroBoard::new("4k3/8/8/8/8/8/8/R3K3 b - - 0 1", "", "P").unwrap(),
+                "Pa1a2",
+                Err(IllegalUnMove::PawnOnBackRank),
+            ),
+            (
+                RetroBoard::new_no_pockets("1R6/7k/8/8/8/8/8/1K6 b - - 0 1").unwrap(),
+                "Ub8b7",
+                Err(IllegalUnMove::NoUnPromotion),
+            ),
+            (
+                RetroBoard::new_no_pockets("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap(),
+                "e1d1",
+                Err(IllegalUnMove::EnPassantSquare),
+            ),
+            (
+                RetroBoard::new_no_pockets("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap(),
+                "e4e2",
+                Ok(()),
+            ),
+            (
+                RetroBoard::new_no_pockets("k7/1N6/8/8/8/8/8/4K2B b - - 0 1").unwrap(),
+                "b7d6",
+                Err(IllegalUnMove::ExposesKing),
+            ),
+            (
+                RetroBoard::new_no_pockets("k7/8/8/8/8/8/8/R3K3 b - - 0 1").unwrap(),
+                "e1e2",
+                Err(IllegalUnMove::CheckNotResolved),
+            ),
+            (
+                RetroBoard::new_no_pockets("k7/8/8/8/8/8/6B1/R3K3 b - - 0 1").unwrap(),
+                "e1e2",
+                Err(IllegalUnMove::CheckNotResolved),
+            ),
+            (
+                RetroBoard::new_no_pockets("3k1r2/8/8/8/8/8/8/5RK1 b - - 0 1").unwrap(),
+                "Ce1h1",
+                Err(IllegalUnMove::CastlingThroughCheck),
+            ),
+            (
+                RetroBoard::new_no_pockets("3k4/8/8/8/8/8/8/5RK1 b - - 0 1").unwrap(),
+                "Ce1h1",
+                Ok(()),
+            ),
+        ] {
+            assert_eq!(r.check_unmove(&u(unmove)), expected, "{unmove} in {r:?}");
+            assert_eq!(r.is_legal_unmove(&u(unmove)), expected.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_check_unmove_any_unmove() {
+        let mut kinds = vec![Normal, EnPassant, UnCastling, UnPromotion(None)];
+        for role in [
+            Role::Pawn,
+            Role::Knight,
+            Role::Bishop,
+            Role::Rook,
+            Role::Queen,
+        ] {
+            kinds.push(Uncapture(role));
+            kinds.push(UnPromotion(Some(role)));
+        }
+        for fen in [
+            "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+            "k7/8/8/8/8/8/6B1/R3K3 b - - 0 1",
+        ] {
+            let r = RetroBoard::new(fen, "PNBRQ1", "PNBRQ1").unwrap();
+            let legal = r.legal_unmoves();
+            for from in Square::ALL {
+                for to in Square::ALL {
+                    for kind in &kinds {
+                        let m = UnMove::new(from, to, *kind);
+                        assert_eq!(r.check_unmove(&m).is_ok(), legal.contains(&m), "{m:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_forward_move(r: &RetroBoard, depth: u32) {
+        if depth == 0 {
+            return;
+        }
+        for m in r.legal_unmoves() {
+            let forward = r.forward_move(&m);
+            let mut child = *r;
+            assert_eq!(child.push(&m).forward_move(&m), forward);
+            if let Some(mut pos) = try_from(child) {
+                assert!(
+                    pos.is_legal(&forward),
+                    "{forward:?} from {m:?} in {child:?}"
+                );
+                pos.play_unchecked(&forward);
+                assert_eq!(pos.board(), &r.board(), "{forward:?} from {m:?}");
+                assert_eq!(pos.turn(), !r.retro_turn());
+            }
+            check_forward_move(&child, depth - 1);
+        }
+    }
+
+    #[test]
+    fn test_forward_move() {
+        for (fen, pocket_white, pocket_black) in [
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "", "NP"),
+            ("1R6/7k/8/8/8/8/8/1K6 b - - 0 1", "", "PQ3"),
+        ] {
+            check_forward_move(
+                &RetroBoard::new(fen, pocket_white, pocket_black).unwrap(),
+                3,
+            );
+        }
+        let mut chess960 = RetroBoard::new_no_pockets("2kr4/8/8/8/8/8/8/2KR4 w - - 0 1").unwrap();
+        chess960.castling_mode = CastlingMode::Chess960;
+        check_forward_move(&chess960, 3);
+    }
+
+    #[test]
+    fn test_from_move_legal() {
+        for fen in [
+            "r3k2r/1P4P1/8/3pP3/8/8/6P1/R3K2R w KQkq d6 0 1",
+            "r3k2r/1P6/8/8/2Pp4/8/6p1/R3K2R b KQkq c3 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let pos: Chess = fen
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap();
+            for m in pos.legal_moves() {
+                let unmove = UnMove::from_move(&m).unwrap();
+                let mut after = pos.clone();
+                after.play_unchecked(&m);
+                let mut r = RetroBoard::from(after);
+                r.pockets = CompactPockets::try_from(
+                    &RetroPockets::from_str("PPPPPPPPNNBBRRQ8", "PPPPPPPPNNBBRRQ8").unwrap(),
+                )
+                .unwrap();
+                assert_eq!(r.check_unmove(&unmove), Ok(()), "{m:?} in {r:?}");
+                assert_eq!(r.forward_move(&unmove), m);
+            }
+        }
+    }
+
+    fn check_push_pop(r: &mut RetroBoard, depth: u32) {
+        if depth == 0 {
+            return;
+        }
+        let before = *r;
+        for m in r.legal_unmoves() {
+            let undo = r.push(&m);
+            assert_eq!(r.zobrist, r.compute_zobrist(), "{m:?}");
+            check_push_pop(r, depth - 1);
+            r.pop(&m, undo);
+            assert_eq!(*r, before, "{m:?}");
+            assert_eq!(r.halfmoves, before.halfmoves);
+            assert_eq!(r.zobrist, before.zobrist);
+        }
+    }
+
+    #[test]
+    fn test_push_pop() {
+        for (fen, pocket_white, pocket_black) in [
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "", "NP"),
+            ("r1k1r3/8/8/8/8/8/8/R2KR3 w - - 0 1", "", ""),
+            ("1R6/7k/8/8/8/8/8/1K6 b - - 0 1", "", "PQ3"),
+        ] {
+            check_push_pop(
+                &mut RetroBoard::new(fen, pocket_white, pocket_black).unwrap(),
+                3,
+            );
+        }
+        let mut r =
+            RetroBoard::new_with_move_counters("2k5/8/8/8/8/8/4P3/2KR4 w - - 1 2", "", "").unwrap();
+        check_push_pop(&mut r, 3);
+    }
+
     #[test]
     fn test_push_unpromote() {
         for i in 1..9 {
@@ -967,28 +3179,33 @@ mod tests {
         )
     }
 
-    fn move_legal(r: &RetroBoard, pos: Chess, unmove: UnMove) -> bool {
+    fn move_legal(r: &RetroBoard, pos: &Chess, unmove: &UnMove) -> bool {
         pos.is_legal(
             &Uci::from_ascii(
-                format!(
-                    "{}{}{}",
-                    unmove.to,
-                    unmove.from,
-                    if unmove.is_unpromotion() {
-                        r.board
-                            .piece_at(unmove.from)
-                            .unwrap()
-                            .role
-                            .char()
-                            .to_string()
-                    } else {
-                        String::new()
-                    }
-                )
+                if unmove.is_uncastling() {
+                    // king to rook syntax
+                    format!("{}{}", unmove.from, unmove.to)
+                } else {
+                    format!(
+                        "{}{}{}",
+                        unmove.to,
+                        unmove.from,
+                        if unmove.is_unpromotion() {
+                            r.board
+                                .piece_at(unmove.from)
+                                .unwrap()
+                                .role
+                                .char()
+                                .to_string()
+                        } else {
+                            String::new()
+                        }
+                    )
+                }
                 .as_bytes(),
             )
             .expect("Valid uci")
-            .to_move(&pos)
+            .to_move(pos)
             .expect("correct move"),
         )
     }
@@ -1001,7 +3218,7 @@ mod tests {
             } else {
                 RetroBoard::new(fen, white_p, black_p).expect("Valid retroboard")
             };
-            let _: Chess = r.clone().into(); // check if position is legal
+            let _: Chess = r.into(); // check if position is legal
             let mut m1_hashset: HashSet<UnMove> = HashSet::new();
             let mut m2_hashset: HashSet<UnMove> = HashSet::new();
             let mut m2 = UnMoveList::new();
@@ -1012,13 +3229,14 @@ mod tests {
                 }
             }
             match gen_type {
-                "pawn" => r.gen_pawns(&mut m2),
-                "piece" => r.gen_pieces(&mut m2),
-                "unpromotion" => r.gen_unpromotion(&mut m2),
+                "pawn" => r.gen_pawns(&UnMoveFilter::ALL, None, &mut m2),
+                "piece" => r.gen_pieces(&UnMoveFilter::ALL, None, &mut m2),
+                "unpromotion" => r.gen_unpromotion(&UnMoveFilter::ALL, None, &mut m2),
+                "uncastling" => r.gen_uncastling(&mut m2),
                 "pseudo" => r.pseudo_legal_unmoves(&mut m2),
                 "legal" => m2 = r.legal_unmoves(),
                 _ => panic!("Choose proper generation method"),
-            };
+            }
             for x in m2.clone() {
                 assert!(!m2_hashset.contains(&x)); // check for move duplicated
                 m2_hashset.insert(x.clone());
@@ -1034,10 +3252,10 @@ mod tests {
             assert_eq!(m1_hashset, m2_hashset);
             for x in m2.clone() {
                 if gen_type == "legal" {
-                    let mut r_after_unmove = r.clone();
+                    let mut r_after_unmove = r;
                     r_after_unmove.push(&x);
                     let chess_after_unmove: Chess = r_after_unmove.into();
-                    assert!(move_legal(&r, chess_after_unmove, x));
+                    assert!(move_legal(&r, &chess_after_unmove, &x));
                 }
             }
         }
@@ -1095,6 +3313,104 @@ mod tests {
         pseudo_legal_double_check_bishop_knight_possible, "8/8/8/8/8/5k2/8/K3N2B b - - 0 1", "", "" ,"pseudo", "a1a2 a1b2 a1b1 e1c2 e1d3 e1g2 h1g2",
     }
 
+    gen_tests_unmoves_no_pockets! {
+        uncastling_king_side, "4k3/8/8/8/8/8/8/5RK1 b - - 0 1", "uncastling", "Ce1h1",
+        uncastling_queen_side, "4k3/8/8/8/8/8/8/2KR4 b - - 0 1", "uncastling", "Ce1a1",
+        uncastling_path_blocked, "4k3/8/8/8/8/8/8/1NKR4 b - - 0 1", "uncastling", "",
+        uncastling_rook_missing, "4k3/8/8/8/8/8/8/2K5 b - - 0 1", "uncastling", "",
+        uncastling_opponent_rook, "4k3/8/8/8/8/8/8/5bK1 b - - 0 1", "uncastling", "",
+    }
+
+    gen_tests_unmoves_no_pockets! {
+        uncastling_legal, "4k3/8/8/8/8/8/6PP/5RK1 b - - 0 1", "legal", "Ce1h1 g1h1 g1f2 f1f2 f1f3 f1f4 f1f5 f1f6 f1f7 f1d1 f1c1 f1b1 f1a1",
+        uncastling_resolves_check, "5k2/8/8/8/8/8/6PP/5RK1 b - - 0 1", "legal", "Ce1h1 g1f2 f1e1 f1d1 f1c1 f1b1 f1a1",
+        uncastling_through_check, "3rk3/8/8/8/8/8/PP6/2KR4 b - - 0 1", "legal", "c1b1 c1d2 c1c2 d1f1 d1g1 d1h1 d1d2 d1d3 d1d4 d1d5 d1d6 d1d7",
+        uncastling_giving_check, "7k/8/8/8/8/8/6P1/5RK1 b - - 0 1", "legal", "g1h1 g1h2 g1f2 f1e1 f1d1 f1c1 f1b1 f1a1 f1f2 f1f3 f1f4 f1f5 f1f6 f1f7",
+    }
+
+    gen_tests_unmoves_no_pockets! {
+        castling_rights_king_and_rook_frozen, "r3k3/8/8/8/8/8/8/1n2K3 w q - 0 1", "legal", "b1a3 b1c3 b1d2",
+        castling_rights_other_rook_free, "r3k2r/8/8/8/8/8/8/4K3 w q - 0 1", "legal", "h8h7 h8h6 h8h5 h8h4 h8h3 h8h2 h8g8 h8f8",
+        castling_rights_opponent_free, "r3k3/8/8/8/8/8/8/R3K3 w Q - 0 1", "legal", "a8a7 a8a6 a8a5 a8a4 a8a3 a8a2 a8b8 a8c8 a8d8 e8d8 e8d7 e8e7 e8f7 e8f8",
+    }
+
+    gen_tests_unmoves! {
+        castling_rights_uncaptures, "r3k3/8/8/8/8/8/8/1n2K3 w q - 0 1", "PNBRQ", "", "legal", "b1a3 b1c3 b1d2 Nb1d2 Bb1d2 Rb1d2 Qb1d2 Nb1a3 Nb1c3 Bb1a3 Bb1c3 Rb1a3 Rb1c3 Qb1a3 Qb1c3",
+    }
+
+    #[test]
+    fn test_push_uncastling() {
+        let mut r = RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/2KR4 b - - 0 1").unwrap();
+        r.push(&u("Ce1a1"));
+        assert_eq!(r.castling_rights(), Bitboard::from(Square::A1));
+        assert_eq!(
+            r.board(),
+            Board::from_ascii_board_fen(b"4k3/8/8/8/8/8/8/R3K3").unwrap()
+        );
+        assert_eq!(r.retro_turn, Black);
+        let chess: Chess = r.into();
+        assert_eq!(
+            chess.castles().castling_rights(),
+            Bitboard::from(Square::A1)
+        );
+    }
+
+    #[test]
+    fn test_uncastling_chess960() {
+        let setup: Setup = "4k3/8/8/8/8/8/8/5RK1 b - - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_setup();
+        let r = RetroBoard::from_setup(setup.clone(), CastlingMode::Standard).unwrap();
+        let mut moves = UnMoveList::new();
+        r.gen_uncastling(&mut moves);
+        assert_eq!(moves.as_slice(), &[u("Ce1h1")]);
+
+        let r = RetroBoard::from_setup(setup, CastlingMode::Chess960).unwrap();
+        let mut moves = UnMoveList::new();
+        r.gen_uncastling(&mut moves);
+        assert_eq!(moves.len(), 21);
+        for m in ["Cb1c1", "Ce1h1", "Cf1g1", "Cg1h1"] {
+            assert!(moves.contains(&u(m)));
+        }
+        // the rook going back to the e-file would give check
+        let uncastlings: Vec<UnMove> = r
+            .legal_unmoves()
+            .into_iter()
+            .filter(UnMove::is_uncastling)
+            .collect();
+        assert_eq!(uncastlings.len(), 18);
+        assert!(!uncastlings.contains(&u("Cb1e1")));
+        for m in uncastlings {
+            let mut r2 = r;
+            r2.push(&m);
+            assert_eq!(r2.castling_rights(), Bitboard::from(m.to));
+            let mut chess: Chess = r2.into();
+            let castle = Move::Castle {
+                king: m.from,
+                rook: m.to,
+            };
+            assert!(chess.is_legal(&castle));
+            chess.play_unchecked(&castle);
+            assert_eq!(chess.board(), &r.board());
+        }
+    }
+
+    #[test]
+    fn test_chess960_castling_mode() {
+        let fen = "4k3/8/8/8/8/8/8/1R4KR w B - 0 1";
+        let r = RetroBoard::new_no_pockets(fen).unwrap();
+        assert_eq!(r.castling_mode(), CastlingMode::Chess960);
+        assert_eq!(r.castling_rights(), Bitboard::from(Square::B1));
+        let chess: Chess = fen
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Chess960)
+            .unwrap();
+        assert_eq!(RetroBoard::from(chess), r);
+        assert_eq!(Chess::from(r).castles().mode(), CastlingMode::Chess960);
+    }
+
     #[test]
     fn test_final_pseudo_unmoves() {
         for mirrored in [false, true] {
@@ -1112,7 +3428,7 @@ mod tests {
             r.pseudo_legal_unmoves(&mut unmove_list_1);
             for m in unmove_list_1 {
                 counter += 1;
-                let mut r2 = r.clone();
+                let mut r2 = r;
                 r2.push(&m);
                 let mut unmove_list_2 = UnMoveList::new();
                 r2.pseudo_legal_unmoves(&mut unmove_list_2);
@@ -1160,6 +3476,13 @@ mod tests {
         no_en_passant_sq_blocked_below_legal, "4k1b1/4p3/4P3/8/8/n7/Kn6/nn6 b - - 0 1","", "P", "legal", "Pe6d5 Pe6f5 a2b3 Pa2b3 e6e5",
         no_en_passant_opposite_check, "3k4/8/5P1n/6B1/5n1n/8/nn6/Kn6 b - - 0 1","", "P", "legal", "Pf6e5",
         en_passant_double_check, "8/4k3/5P2/8/8/8/nn2R3/Kn6 b - - 0 1","", "P", "legal", "Ef6e5 Pf6e5",
+        double_check_closest_blocker_uncaptures, "8/8/8/8/R7/2Q5/1B6/k1K5 b - -","PNBRQ", "PNBRQ", "legal", "Bb2a3 Nb2a3 Pb2a3 Qb2a3 Rb2a3",
+        double_check_unpromotion_uncapturing_blocker, "1k3Q1R/6q1/Kn6/nn3p2/8/8/8/8 b - -","PPNBR2", "PPNBR2", "legal", "Bf8a3 Bf8b4 Bf8c5 Bf8e7 Bf8f6 Bf8f7 Bf8g8 Nf8a3 Nf8b4 Nf8c5 Nf8e7 Nf8f6 Nf8f7 Nf8g8 Rf8a3 Rf8b4 Rf8c5 Rf8e7 Rf8f6 Rf8f7 Rf8g8 UBf8e7 UNf8e7 URf8e7",
+        king_cannot_unpromote, "8/8/8/8/R7/B1Q5/1r6/k1K5 w - -","PPNBRQ2", "PPNBQ2", "legal", "Ba1a2 Bb2a2 Bb2b3 Bb2b4 Bb2b5 Bb2b6 Bb2b7 Bb2b8 Bb2d2 Bb2e2 Bb2f2 Bb2g2 Bb2h2 Na1a2 Nb2a2 Nb2b3 Nb2b4 Nb2b5 Nb2b6 Nb2b7 Nb2b8 Nb2d2 Nb2e2 Nb2f2 Nb2g2 Nb2h2 Pb2a2 Pb2b3 Pb2b4 Pb2b5 Pb2b6 Pb2b7 Pb2b8 Pb2d2 Pb2e2 Pb2f2 Pb2g2 Pb2h2 Qa1a2 Qb2a2 Qb2b3 Qb2b4 Qb2b5 Qb2b6 Qb2b7 Qb2b8 Qb2d2 Qb2e2 Qb2f2 Qb2g2 Qb2h2 Ra1a2 Rb2a2 Rb2b3 Rb2b4 Rb2b5 Rb2b6 Rb2b7 Rb2b8 Rb2d2 Rb2e2 Rb2f2 Rb2g2 Rb2h2 a1a2 b2a2 b2b3 b2b4 b2b5 b2b6 b2b7 b2b8 b2d2 b2e2 b2f2 b2g2 b2h2",
+        en_passant_double_check_pawn_still_checking, "8/8/4P1r1/2R2k2/3p1rP1/1P1Kp2p/1R2P3/8 b - - 0 1","", "PNBRQ", "legal", "",
+        en_passant_double_check_queen_still_checking, "q4b2/1p6/3PNb2/2Q3k1/2n3P1/8/3PBqQr/2KR4 b - -","PPNBR", "PPPR", "legal", "",
+        en_passant_uncaptured_pawn_blocks_bishop, "b4Br1/5n2/kP3p2/5P2/pP2p1pP/P1p1p3/2P1B2R/4K2N b - -","NBRQ", "PPNBRQ1", "legal", "Be2d1 Be2f1 Be2f3 Eb6a5 Eb6c5 Ne2d1 Ne2f1 Ne2f3 Pe2d1 Pe2f1 Pe2f3 Qe2d1 Qe2f1 Qe2f3 Re2d1 Re2f1 Re2f3 e2d1 e2f3",
+        en_passant_uncaptured_pawn_blocks_rook, "6q1/2Q5/RQ6/2r5/n7/BRp5/kN6/B1K5 w - -","P", "B", "legal", "Ec3b4 Ec3d4 Pc3b4 Pc3d4 Pc5a5 Pc5b5 Pc5c4 Pc5c6 Pc5d5 Pc5e5 Pc5f5 Pc5g5 Pc5h5 c3c4 c5a5 c5b5 c5c4 c5c6 c5d5 c5e5 c5f5 c5g5 c5h5 g8a8 g8b8 g8c4 g8c8 g8d5 g8d8 g8e6 g8e8 g8f7 g8f8 g8g2 g8g3 g8g4 g8g6 g8g7 g8h7 g8h8",
     }
 
     #[test]
@@ -1175,19 +3498,19 @@ mod tests {
             } else {
                 RetroBoard::new(fen, white_p, black_p).expect("Valid retroboard")
             };
-            let _: Chess = r.clone().into(); // check if position is legal
+            let _: Chess = r.into(); // check if position is legal
             for m in r.legal_unmoves() {
                 counter += 1;
-                let mut r2 = r.clone();
+                let mut r2 = r;
                 r2.push(&m);
-                let chess_after_unmove: Chess = r2.clone().into();
-                assert!(move_legal(&r, chess_after_unmove, m));
+                let chess_after_unmove: Chess = r2.into();
+                assert!(move_legal(&r, &chess_after_unmove, &m));
                 for m2 in r2.legal_unmoves() {
                     counter += 1;
-                    let mut r3 = r2.clone();
+                    let mut r3 = r2;
                     r3.push(&m2);
-                    let chess_after_unmove2: Chess = r3.clone().into();
-                    assert!(move_legal(&r2, chess_after_unmove2, m2));
+                    let chess_after_unmove2: Chess = r3.into();
+                    assert!(move_legal(&r2, &chess_after_unmove2, &m2));
                 }
             }
             assert_eq!(counter, 3975)
@@ -1197,37 +3520,38 @@ mod tests {
     // same as Chess::from(RetroBoard) but not panicking if not valid position
     // Note that if a `RetroBoard` validely contains an invalid `Chess` position it is a bug
     fn try_from(rboard: RetroBoard) -> Option<Chess> {
-        Chess::from_setup(Setup::from(rboard), CastlingMode::Standard)
+        let mode = rboard.castling_mode;
+        Chess::from_setup(Setup::from(rboard), mode)
             .or_else(PositionError::ignore_impossible_check)
             .ok()
     }
 
     // does not take into account internal positions, contrary to `test_final_unmoves`
     // As it names says it is behavior identical to `perft` but with additional debug built-in in case it fails
-    fn perft_debug(r: RetroBoard, depth: u32) -> Option<u64> {
+    fn perft_debug(r: &RetroBoard, depth: u32) -> Option<u64> {
         if depth < 1 {
             Some(1)
         } else {
-            try_from(r.clone())?; // check if position is legal
+            try_from(*r)?; // check if position is legal
             let mut acc: u64 = 0;
             for m in r.legal_unmoves() {
-                let mut r2 = r.clone();
+                let mut r2 = *r;
                 r2.push(&m);
-                let chess_after_unmove: Chess = match try_from(r2.clone()) {
+                let chess_after_unmove: Chess = match try_from(r2) {
                     None => {
                         println!("depth {depth}, Illegal pos {r2:?}, move leading to it {m:?}");
                         return None;
                     }
                     Some(pos) => pos,
                 };
-                assert!(move_legal(&r, chess_after_unmove, m.clone()));
-                match perft_debug(r2.clone(), depth - 1) {
+                assert!(move_legal(r, &chess_after_unmove, &m));
+                match perft_debug(&r2, depth - 1) {
                     None => {
                         println!("depth {depth}, Illegal pos {r2:?}, move leading to it {m:?}");
                         return None;
                     }
                     Some(x) => acc += x,
-                };
+                }
             }
             Some(acc)
         }
@@ -1245,15 +3569,299 @@ mod tests {
             } else {
                 RetroBoard::new(fen, white_p, black_p).expect("Valid retroboard")
             };
-            assert!(
-                perft_debug(r.clone(), 0) == Some(perft(&r, 0)) && Some(perft(&r, 0)) == Some(1)
+            assert!(perft_debug(&r, 0) == Some(perft(&r, 0)) && Some(perft(&r, 0)) == Some(1));
+            assert!(perft_debug(&r, 1) == Some(perft(&r, 1)) && Some(perft(&r, 1)) == Some(24));
+            assert!(perft_debug(&r, 2) == Some(perft(&r, 2)) && Some(perft(&r, 2)) == Some(3951));
+        }
+    }
+
+    fn check_legal_unmoves_variants(r: &RetroBoard, depth: u32) {
+        let moves = r.legal_unmoves();
+        let mut vec_moves: Vec<UnMove> = Vec::new();
+        r.legal_unmoves_into(&mut vec_moves);
+        assert_eq!(vec_moves.as_slice(), moves.as_slice());
+        let mut packed_moves = PackedUnMoveList::new();
+        r.legal_unmoves_into(&mut packed_moves);
+        assert!(packed_moves
+            .into_iter()
+            .map(UnMove::from)
+            .eq(moves.iter().cloned()));
+        assert_eq!(r.count_legal_unmoves(), moves.len());
+        let ctx = r.retro_context();
+        let mut pseudo = UnMoveList::new();
+        r.pseudo_legal_unmoves(&mut pseudo);
+        for m in &pseudo {
+            assert_eq!(
+                r.is_legal_unmove_with(&ctx, m),
+                moves.contains(m),
+                "{r:?} {m:?}"
+            );
+        }
+        // the legality masks must agree with checking each pseudo legal unmove
+        if !ctx.checkers().more_than_one() {
+            let safe: HashSet<&UnMove> = pseudo
+                .iter()
+                .filter(|m| {
+                    if m.is_uncastling() {
+                        r.uncastling_safety(&ctx, m).is_ok()
+                    } else {
+                        r.safety(&ctx, m).is_ok()
+                    }
+                })
+                .collect();
+            assert_eq!(safe, moves.iter().collect::<HashSet<_>>(), "{r:?}");
+        }
+        let mut filters: Vec<UnMoveFilter> = r
+            .board
+            .occupied()
+            .into_iter()
+            .map(|sq| UnMoveFilter {
+                from: sq.into(),
+                ..UnMoveFilter::ALL
+            })
+            .collect();
+        for kind in [
+            MoveKindMask::QUIET,
+            MoveKindMask::UNCAPTURE,
+            MoveKindMask::UNPROMOTION,
+            MoveKindMask::EN_PASSANT,
+            MoveKindMask::UNCASTLING,
+        ] {
+            filters.push(UnMoveFilter {
+                kinds: kind,
+                ..UnMoveFilter::ALL
+            });
+            filters.push(UnMoveFilter {
+                kinds: !kind,
+                to: Bitboard::LIGHT_SQUARES,
+                ..UnMoveFilter::ALL
+            });
+        }
+        for role in Role::ALL {
+            let mut roles = ByRole::default();
+            *roles.get_mut(role) = true;
+            filters.push(UnMoveFilter {
+                roles,
+                ..UnMoveFilter::ALL
+            });
+        }
+        for filter in filters {
+            let expected: HashSet<&UnMove> = moves
+                .iter()
+                .filter(|m| {
+                    let role = if m.is_uncastling() {
+                        Role::King
+                    } else {
+                        r.board.role_at(m.from).unwrap()
+                    };
+                    filter.matches(m, role)
+                })
+                .collect();
+            let filtered = r.legal_unmoves_filtered(&filter);
+            assert_eq!(filtered.len(), expected.len(), "{r:?} {filter:?}");
+            assert_eq!(filtered.iter().collect::<HashSet<_>>(), expected, "{r:?}");
+        }
+        let iter_moves: Vec<UnMove> = r.legal_unmoves_iter().collect();
+        assert_eq!(iter_moves.len(), moves.len(), "{r:?}");
+        assert_eq!(
+            iter_moves.iter().collect::<HashSet<_>>(),
+            moves.iter().collect::<HashSet<_>>(),
+            "{r:?}"
+        );
+        if depth > 0 {
+            for m in moves {
+                let mut r2 = *r;
+                r2.push(&m);
+                check_legal_unmoves_variants(&r2, depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_legal_unmoves_iter() {
+        for (fen, pocket_white, pocket_black) in [
+            (
+                "q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1",
+                "2PNBRQ",
+                "3NBRQP",
+            ),
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "", "NP"),
+            ("8/8/8/8/R7/2Q5/1B6/k1K5 b - - 0 1", "PNBRQ", "PNBRQ"),
+        ] {
+            check_legal_unmoves_variants(
+                &RetroBoard::new(fen, pocket_white, pocket_black).unwrap(),
+                2,
+            );
+        }
+        for fen in [
+            "4k3/4p3/8/8/4P3/8/8/R3K1N1 b - - 2 10",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        ] {
+            check_legal_unmoves_variants(
+                &RetroBoard::new_with_move_counters(fen, "PN", "PN").unwrap(),
+                2,
+            );
+        }
+    }
+
+    #[test]
+    fn test_perft_divide_and_stats() {
+        for (fen, pocket_white, pocket_black) in [
+            (
+                "q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1",
+                "2PNBRQ",
+                "3NBRQP",
+            ),
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+        ] {
+            let r = RetroBoard::new(fen, pocket_white, pocket_black).unwrap();
+            for depth in 1..4 {
+                let nodes = perft(&r, depth);
+                let divide = perft_divide(&r, depth);
+                assert_eq!(divide.len(), r.legal_unmoves().len());
+                assert_eq!(divide.iter().map(|(_, n)| n).sum::<u64>(), nodes);
+                let stats = perft_stats(&r, depth);
+                assert_eq!(stats.leaves, nodes);
+                let uncaptures: u64 = Role::ALL.iter().map(|&r| stats.uncaptures.get(r)).sum();
+                assert_eq!(
+                    stats.normal
+                        + uncaptures
+                        + stats.unpromotions
+                        + stats.en_passant
+                        + stats.uncastling,
+                    nodes
+                );
+                assert!(stats.checks <= nodes);
+            }
+        }
+        let stats = perft_stats(
+            &RetroBoard::new("r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1", "PNBRQ1", "").unwrap(),
+            1,
+        );
+        assert_eq!(stats.uncastling, 1);
+        assert_eq!(stats.en_passant, 0);
+        assert_eq!(stats.uncaptures, ByRole::default());
+        assert_eq!(
+            perft_divide(
+                &RetroBoard::new_no_pockets("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap(),
+                0
+            ),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_hashed_perft() {
+        for (fen, pocket_white, pocket_black) in [
+            (
+                "q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1",
+                "2PNBRQ",
+                "3NBRQP",
+            ),
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+        ] {
+            let r = RetroBoard::new(fen, pocket_white, pocket_black).unwrap();
+            for depth in 0..4 {
+                let nodes = perft(&r, depth);
+                assert_eq!(hashed_perft(&r, depth, 1), nodes);
+                assert_eq!(hashed_perft(&r, depth, 1 << 12), nodes);
+            }
+        }
+        for fen in [
+            "4k3/4p3/8/8/4P3/8/8/R3K1N1 b - - 2 10",
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+        ] {
+            let r = RetroBoard::new_with_move_counters(fen, "PN", "PN").unwrap();
+            for depth in 0..5 {
+                assert_eq!(hashed_perft(&r, depth, 1 << 12), perft(&r, depth));
+            }
+        }
+    }
+
+    fn collect_predecessors(r: &RetroBoard, depth: u32, positions: &mut HashSet<RetroBoard>) {
+        if depth == 0 {
+            positions.insert(*r);
+        } else {
+            for m in r.legal_unmoves() {
+                let mut r2 = *r;
+                r2.push(&m);
+                collect_predecessors(&r2, depth - 1, positions);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unique_predecessors() {
+        for (fen, pocket_white, pocket_black) in [
+            ("8/8/8/8/5k2/8/3K4/8 b - - 0 1", "NB", "NB"),
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+        ] {
+            let r = RetroBoard::new(fen, pocket_white, pocket_black).unwrap();
+            let counts = count_unique_predecessors(&r, 3, None).unwrap();
+            for depth in 0..4 {
+                let mut positions = HashSet::new();
+                collect_predecessors(&r, depth, &mut positions);
+                assert_eq!(counts[depth as usize], positions.len());
+                assert_eq!(unique_predecessors(&r, depth, None).unwrap(), positions);
+                assert!(positions.len() as u64 <= perft(&r, depth));
+            }
+            assert_eq!(
+                count_unique_predecessors(&r, 3, Some(counts[2])),
+                Err(PositionLimitExceeded {
+                    depth: 3,
+                    counts: counts[..3].to_vec()
+                })
             );
-            assert!(
-                perft_debug(r.clone(), 1) == Some(perft(&r, 1)) && Some(perft(&r, 1)) == Some(24)
+            assert_eq!(
+                unique_predecessors(&r, 2, Some(counts[2] - 1)),
+                Err(PositionLimitExceeded {
+                    depth: 2,
+                    counts: counts[..2].to_vec()
+                })
             );
-            assert!(
-                perft_debug(r.clone(), 2) == Some(perft(&r, 2)) && Some(perft(&r, 2)) == Some(3951)
+            assert_eq!(
+                unique_predecessors(&r, 2, Some(counts[2])).unwrap().len(),
+                counts[2]
             );
         }
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_perft() {
+        for (fen, pocket_white, pocket_black) in [
+            (
+                "q4N2/1p5k/8/8/6P1/4Q3/1K1PB3/7r b - - 0 1",
+                "2PNBRQ",
+                "3NBRQP",
+            ),
+            (
+                "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+                "PNBRQ1",
+                "PNBRQ1",
+            ),
+        ] {
+            let r = RetroBoard::new(fen, pocket_white, pocket_black).unwrap();
+            for depth in 0..4 {
+                assert_eq!(par_perft(&r, depth), perft(&r, depth));
+            }
+        }
+    }
 }
@@ -9,7 +9,8 @@ use shakmaty::{
     Role,
 };
 
-/// Error when parsing an invalid retro UCI.
+/// Error when parsing an invalid [`RetroPocket`], or when a pocket counter is above 15 for a [`RetroBoard`](crate::RetroBoard).
+/// It is converted into [`ParseFenError::InvalidPocket`].
 #[derive(Copy, Clone, Debug)]
 pub struct ParseRetroPocketError;
 
@@ -29,10 +30,10 @@ impl fmt::Display for ParseRetroPocketError {
 impl Error for ParseRetroPocketError {}
 
 /// A [`RetroBoard`](crate::RetroBoard) pocket with a counter for each piece type.
-/// It stores the pieces than can be uncaptured by each color.    
+/// It stores the pieces than can be uncaptured by each color.
 /// `self.unpromotion` is the number of pieces than can unpromote into a pawn.
 /// By default it is set to 0
-#[derive(Eq, PartialEq, Clone, Hash)]
+#[derive(Eq, PartialEq, Clone, Copy, Hash)]
 pub struct RetroPocket {
     pub pawn: u8,
     pub knight: u8,
@@ -59,6 +60,35 @@ impl RetroPocket {
             _ => panic!("Attempt to decrement a pocket role whose value is already 0"),
         }
     }
+
+    /// Increment the corresponding pocket role, undoing [`RetroPocket::decr`].
+    /// # Panics
+    /// Panics if called with the King.
+    #[inline]
+    pub fn incr(&mut self, role: Role) {
+        match role {
+            Role::Pawn => self.pawn += 1,
+            Role::Knight => self.knight += 1,
+            Role::Bishop => self.bishop += 1,
+            Role::Rook => self.rook += 1,
+            Role::Queen => self.queen += 1,
+            Role::King => panic!("Cannot uncapture king"),
+        }
+    }
+
+    /// Number of pieces of that [`Role`](shakmaty::Role) in the pocket, always 0 for the King.
+    #[inline]
+    #[must_use]
+    pub fn get(&self, role: Role) -> u8 {
+        match role {
+            Role::Pawn => self.pawn,
+            Role::Knight => self.knight,
+            Role::Bishop => self.bishop,
+            Role::Rook => self.rook,
+            Role::Queen => self.queen,
+            Role::King => 0,
+        }
+    }
 }
 
 impl Default for RetroPocket {
@@ -158,25 +188,25 @@ impl IntoIterator for RetroPocket {
         let mut v: ArrayVec<Role, 5> = ArrayVec::new();
         if self.pawn > 0 {
             v.push(Role::Pawn)
-        };
+        }
         if self.knight > 0 {
             v.push(Role::Knight)
-        };
+        }
         if self.bishop > 0 {
             v.push(Role::Bishop)
-        };
+        }
         if self.rook > 0 {
             v.push(Role::Rook)
-        };
+        }
         if self.queen > 0 {
             v.push(Role::Queen)
-        };
+        }
         v.into_iter()
     }
 }
 
 /// Wrapper around [`RetroPocket`] that provide handful functions to access them by color
-#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Hash)]
 pub struct RetroPockets {
     // TODO check if worth switching to `ByColor`
     black: RetroPocket,
@@ -268,12 +298,8 @@ mod tests {
         for conf in &["PNB", "BRQ", "PNBRQ"] {
             // need to be in the right order
             let r = RetroPocket::from_str(conf).unwrap();
-            println!("{:?}", r.clone().into_iter());
-            for (x, y) in conf
-                .chars()
-                .map(|c| Role::from_char(c).unwrap())
-                .zip(r.into_iter())
-            {
+            println!("{:?}", r.into_iter());
+            for (x, y) in conf.chars().map(|c| Role::from_char(c).unwrap()).zip(r) {
                 assert_eq!(x, y)
             }
         }
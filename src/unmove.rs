@@ -1,15 +1,71 @@
-use std::fmt;
+use std::{
+    fmt,
+    ops::{BitAnd, BitOr, Not},
+};
 
 use arrayvec::ArrayVec;
 use lazy_static::lazy_static;
 use regex::Regex;
-use shakmaty::{Role, Square};
+use shakmaty::{Bitboard, ByRole, CastlingSide, Move, Role, Square};
 
 /// A container for unmoves that can be stored inline on the stack.
 ///
 /// The capacity is limited, but there is enough space to hold the legal unmoves of any chess position.
 pub type UnMoveList = ArrayVec<UnMove, 512>; // TODO check if reducing that number is possible (256 used for std in shakmaty)
 
+/// Receives the unmoves as they are generated, see [`RetroBoard::legal_unmoves_into`](crate::RetroBoard::legal_unmoves_into).
+///
+/// Implemented for [`UnMoveList`] and [`Vec`], for [`Bitboard`] which collects the squares the pieces go back to,
+/// and for closures taking an [`UnMove`].
+pub trait UnMoveSink {
+    fn push(&mut self, m: UnMove);
+}
+
+impl<const CAP: usize> UnMoveSink for ArrayVec<UnMove, CAP> {
+    #[inline]
+    fn push(&mut self, m: UnMove) {
+        ArrayVec::push(self, m);
+    }
+}
+
+impl UnMoveSink for Vec<UnMove> {
+    #[inline]
+    fn push(&mut self, m: UnMove) {
+        Vec::push(self, m);
+    }
+}
+
+impl UnMoveSink for Bitboard {
+    #[inline]
+    fn push(&mut self, m: UnMove) {
+        self.add(m.to);
+    }
+}
+
+/// A container for [`PackedUnMove`]s that can be stored inline on the stack, using 2 bytes per unmove.
+pub type PackedUnMoveList = ArrayVec<PackedUnMove, 512>;
+
+impl<const CAP: usize> UnMoveSink for ArrayVec<PackedUnMove, CAP> {
+    #[inline]
+    fn push(&mut self, m: UnMove) {
+        ArrayVec::push(self, m.into());
+    }
+}
+
+impl UnMoveSink for Vec<PackedUnMove> {
+    #[inline]
+    fn push(&mut self, m: UnMove) {
+        Vec::push(self, m.into());
+    }
+}
+
+impl<F: FnMut(UnMove)> UnMoveSink for F {
+    #[inline]
+    fn push(&mut self, m: UnMove) {
+        self(m);
+    }
+}
+
 /// Error when parsing an invalid retro UCI.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ParseRetroUciError;
@@ -21,6 +77,10 @@ pub enum MoveKind {
     EnPassant,
     UnPromotion(Option<Role>),
     Uncapture(Role),
+    /// The king and the rook go back to the squares they were on before castling.
+    /// In that case [`UnMove::from`] is the square the king goes back to, and [`UnMove::to`] the one of the rook,
+    /// following [`shakmaty::Move::Castle`] convention.
+    UnCastling,
 }
 
 impl MoveKind {
@@ -34,6 +94,7 @@ impl MoveKind {
         match special_move {
             Some("U") => Ok(Self::UnPromotion(role_opt)),
             Some("E") if role_opt.is_none() => Ok(Self::EnPassant),
+            Some("C") if role_opt.is_none() => Ok(Self::UnCastling),
             Some("") if role_opt.is_some() => Ok(Self::Uncapture(role_opt.unwrap())), // if let guard experimental
             Some("") => Ok(Self::Normal),
             _ => Err(ParseRetroUciError),
@@ -55,11 +116,17 @@ impl MoveKind {
         matches!(self, Self::UnPromotion(_))
     }
 
+    #[must_use]
+    pub fn is_uncastling(&self) -> bool {
+        matches!(self, Self::UnCastling)
+    }
+
     #[must_use]
     pub fn to_retro_uci(&self) -> String {
         match self {
             Self::Normal => String::new(),
             Self::EnPassant => "E".to_string(),
+            Self::UnCastling => "C".to_string(),
             Self::Uncapture(role) => role.upper_char().to_string(),
             Self::UnPromotion(role_opt) => {
                 "U".to_string()
@@ -69,6 +136,112 @@ impl MoveKind {
     }
 }
 
+/// Set of [`MoveKind`]s, without their roles. An unpromotion counts as such even when it also uncaptures a piece.
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct MoveKindMask(u8);
+
+impl MoveKindMask {
+    pub const NONE: Self = Self(0);
+    /// [`MoveKind::Normal`]
+    pub const QUIET: Self = Self(1);
+    /// [`MoveKind::Uncapture`]
+    pub const UNCAPTURE: Self = Self(1 << 1);
+    /// [`MoveKind::UnPromotion`]
+    pub const UNPROMOTION: Self = Self(1 << 2);
+    /// [`MoveKind::EnPassant`]
+    pub const EN_PASSANT: Self = Self(1 << 3);
+    /// [`MoveKind::UnCastling`]
+    pub const UNCASTLING: Self = Self(1 << 4);
+    pub const ALL: Self = Self((1 << 5) - 1);
+
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<MoveKind> for MoveKindMask {
+    fn from(kind: MoveKind) -> Self {
+        match kind {
+            MoveKind::Normal => Self::QUIET,
+            MoveKind::Uncapture(_) => Self::UNCAPTURE,
+            MoveKind::UnPromotion(_) => Self::UNPROMOTION,
+            MoveKind::EnPassant => Self::EN_PASSANT,
+            MoveKind::UnCastling => Self::UNCASTLING,
+        }
+    }
+}
+
+impl BitOr for MoveKindMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for MoveKindMask {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for MoveKindMask {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0 & Self::ALL.0)
+    }
+}
+
+/// Restricts the unmoves generated by [`RetroBoard::legal_unmoves_filtered`](crate::RetroBoard::legal_unmoves_filtered).
+///
+/// Build it from [`UnMoveFilter::ALL`] with the fields to restrict, e.g
+/// `UnMoveFilter { from: Square::E4.into(), ..UnMoveFilter::ALL }`.
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct UnMoveFilter {
+    /// Allowed [`UnMove::from`] squares.
+    pub from: Bitboard,
+    /// Allowed [`UnMove::to`] squares.
+    pub to: Bitboard,
+    /// Allowed roles of the piece retracted. Uncastling is a king unmove.
+    pub roles: ByRole<bool>,
+    pub kinds: MoveKindMask,
+}
+
+impl UnMoveFilter {
+    /// Does not filter out anything.
+    pub const ALL: Self = Self {
+        from: Bitboard::FULL,
+        to: Bitboard::FULL,
+        roles: ByRole {
+            pawn: true,
+            knight: true,
+            bishop: true,
+            rook: true,
+            queen: true,
+            king: true,
+        },
+        kinds: MoveKindMask::ALL,
+    };
+
+    /// Whether `m`, retracting a piece of role `role`, is kept by the filter.
+    #[must_use]
+    pub fn matches(&self, m: &UnMove, role: Role) -> bool {
+        self.from.contains(m.from)
+            && self.to.contains(m.to)
+            && *self.roles.get(role)
+            && self.kinds.contains(m.move_kind.into())
+    }
+}
+
 /// Information about a move.
 ///
 /// Called [`UnMove`] not to be confused with [`shakmaty::Move`]. When there is no doubt about which one is reffered to, can be called "move".
@@ -97,7 +270,11 @@ impl UnMove {
     /// e.g "Ed6e5". Note than it's different than "Pd6e5". In the first example, the uncaptured pawn is in `d5`,
     /// while in the second one it's in `d6`.
     ///
-    /// regex: r"\[UE\]?\[NBRQ\]?(\[abcdefgh\]\[1-8\]){2}"
+    /// -Uncastling: "C" then the square where the king goes back and the square where the rook goes back.
+    /// e.g "Ce1h1" for white king side castling. Contrary to other unmoves, the first square is not the one the piece stands on,
+    /// but the syntax is the same as the chess960 UCI of the castling move.
+    ///
+    /// regex: r"\[UEC\]?\[NBRQ\]?(\[abcdefgh\]\[1-8\]){2}"
     ///
     /// Note: A unmove being accepted does not means it is for sure legal, just syntaxically correct
     /// # Examples
@@ -122,12 +299,16 @@ impl UnMove {
     /// assert_eq!(en_passant.to, Square::D4);
     /// assert!(en_passant.is_en_passant());
     /// assert!(!en_passant.is_unpromotion());
+    ///
+    /// let uncastling: UnMove = UnMove::from_retro_uci("Ce1h1").unwrap();
+    /// assert_eq!(uncastling.from, Square::E1);
+    /// assert_eq!(uncastling.to, Square::H1);
+    /// assert!(uncastling.is_uncastling());
     /// ```
-
     #[allow(clippy::doc_markdown)]
     pub fn from_retro_uci(retro_uci: &str) -> Result<UnMove, ParseRetroUciError> {
         lazy_static! {
-        static ref UNMOVE_REGEX: Regex = Regex::new(r"^(?P<special_move>[UE]?)(?P<uncapture>[PNBRQ]?)(?P<from>([abcdefgh][1-8]))(?P<to>([abcdefgh][1-8]))$").unwrap();
+        static ref UNMOVE_REGEX: Regex = Regex::new(r"^(?P<special_move>[UEC]?)(?P<uncapture>[PNBRQ]?)(?P<from>([abcdefgh][1-8]))(?P<to>([abcdefgh][1-8]))$").unwrap();
         }
         UNMOVE_REGEX
             .captures(retro_uci)
@@ -160,6 +341,48 @@ impl UnMove {
         }
     }
 
+    /// Returns the [`UnMove`] retracting a [`shakmaty::Move`], from the position where it has been played.
+    /// Its uncaptured role is the one captured by the move, so the unmove is only legal if the
+    /// [`RetroPocket`](crate::RetroPocket) of the [`RetroBoard`](crate::RetroBoard) contains it.
+    /// Returns `None` for [`Move::Put`], which cannot be retracted.
+    /// # Examples
+    /// ```
+    /// use retroboard::{shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Position}, RetroBoard, UnMove};
+    ///
+    /// let fen: Fen = "4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1".parse().unwrap();
+    /// let pos: Chess = fen.into_position(CastlingMode::Standard).unwrap();
+    /// let m = Uci::from_ascii(b"e4d5").unwrap().to_move(&pos).unwrap();
+    /// let unmove = UnMove::from_move(&m).unwrap();
+    /// assert_eq!(unmove, UnMove::from_retro_uci("Pd5e4").unwrap());
+    ///
+    /// let r = RetroBoard::new("4k3/8/8/3P4/8/8/8/4K3 b - - 0 1", "", "P").unwrap();
+    /// assert!(r.is_legal_unmove(&unmove));
+    /// assert_eq!(r.forward_move(&unmove), m);
+    /// ```
+    #[must_use]
+    pub fn from_move(m: &Move) -> Option<Self> {
+        match *m {
+            Move::Normal {
+                from,
+                capture,
+                to,
+                promotion,
+                ..
+            } => Some(Self::new(
+                to,
+                from,
+                match (promotion, capture) {
+                    (Some(_), capture) => MoveKind::UnPromotion(capture),
+                    (None, Some(role)) => MoveKind::Uncapture(role),
+                    (None, None) => MoveKind::Normal,
+                },
+            )),
+            Move::EnPassant { from, to } => Some(Self::new(to, from, MoveKind::EnPassant)),
+            Move::Castle { king, rook } => Some(Self::new(king, rook, MoveKind::UnCastling)),
+            Move::Put { .. } => None,
+        }
+    }
+
     /// Returns a string following the retro uci standard. See [`UnMove::from_retro_uci`] for more information.
     #[must_use]
     pub fn to_retro_uci(&self) -> String {
@@ -176,7 +399,7 @@ impl UnMove {
     #[must_use]
     pub fn uncapture(&self) -> Option<Role> {
         match self.move_kind {
-            MoveKind::Normal => None,
+            MoveKind::Normal | MoveKind::UnCastling => None,
             MoveKind::Uncapture(role) => Some(role),
             MoveKind::UnPromotion(role_opt) => role_opt,
             MoveKind::EnPassant => Some(Role::Pawn),
@@ -195,6 +418,37 @@ impl UnMove {
         self.move_kind.is_en_passant()
     }
 
+    #[inline]
+    #[must_use]
+    pub fn is_uncastling(&self) -> bool {
+        self.move_kind.is_uncastling()
+    }
+
+    /// If the move is an uncastling, returns the side the king castled to.
+    /// # Examples
+    ///
+    /// ```
+    /// use retroboard::UnMove;
+    /// use shakmaty::CastlingSide;
+    ///
+    /// assert_eq!(
+    ///     UnMove::from_retro_uci("Ce8a8").unwrap().castling_side(),
+    ///     Some(CastlingSide::QueenSide)
+    /// );
+    /// assert_eq!(UnMove::from_retro_uci("e8a8").unwrap().castling_side(), None);
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn castling_side(&self) -> Option<CastlingSide> {
+        self.is_uncastling().then(|| {
+            if self.from < self.to {
+                CastlingSide::KingSide
+            } else {
+                CastlingSide::QueenSide
+            }
+        })
+    }
+
     /// If the move is an uncapture moves, returns the square when the piece uncaptured will land.
     /// It is always the `from` square, except for en-passant move.
     /// # Examples
@@ -249,6 +503,95 @@ impl UnMove {
             move_kind: self.move_kind,
         }
     }
+
+    /// Lossless encoding of the unmove on 16 bits: `from` in the 6 lowest bits, `to` in the next 6
+    /// and the [`MoveKind`] in the 4 highest. Every `u16` is the encoding of exactly one [`UnMove`].
+    /// # Examples
+    ///
+    /// ```
+    /// use retroboard::UnMove;
+    ///
+    /// let unmove = UnMove::from_retro_uci("UNb8a7").unwrap();
+    /// assert_eq!(UnMove::from_u16(unmove.to_u16()), unmove);
+    /// assert_eq!(UnMove::from_u16(0), UnMove::from_retro_uci("a1a1").unwrap());
+    /// ```
+    #[must_use]
+    pub fn to_u16(&self) -> u16 {
+        let kind = match self.move_kind {
+            MoveKind::Normal => 0,
+            MoveKind::Uncapture(role) => role as u16,
+            MoveKind::UnPromotion(None) => 7,
+            MoveKind::UnPromotion(Some(role)) => 7 + role as u16,
+            MoveKind::EnPassant => 14,
+            MoveKind::UnCastling => 15,
+        };
+        self.from as u16 | (self.to as u16) << 6 | kind << 12
+    }
+
+    /// Decodes an unmove encoded with [`UnMove::to_u16`].
+    #[must_use]
+    pub fn from_u16(packed: u16) -> Self {
+        // roles start at 1
+        let role = |i: u16| Role::ALL[usize::from(i) - 1];
+        let move_kind = match packed >> 12 {
+            0 => MoveKind::Normal,
+            kind @ 1..=6 => MoveKind::Uncapture(role(kind)),
+            7 => MoveKind::UnPromotion(None),
+            kind @ 8..=13 => MoveKind::UnPromotion(Some(role(kind - 7))),
+            14 => MoveKind::EnPassant,
+            _ => MoveKind::UnCastling,
+        };
+        Self::new(
+            Square::new(u32::from(packed & 0x3f)),
+            Square::new(u32::from(packed >> 6 & 0x3f)),
+            move_kind,
+        )
+    }
+}
+
+/// An [`UnMove`] stored on 2 bytes, see [`UnMove::to_u16`].
+#[derive(Hash, Eq, PartialEq, Clone, Copy)]
+pub struct PackedUnMove(u16);
+
+impl From<&UnMove> for PackedUnMove {
+    #[inline]
+    fn from(m: &UnMove) -> Self {
+        Self(m.to_u16())
+    }
+}
+
+impl From<UnMove> for PackedUnMove {
+    #[inline]
+    fn from(m: UnMove) -> Self {
+        Self(m.to_u16())
+    }
+}
+
+impl From<PackedUnMove> for UnMove {
+    #[inline]
+    fn from(m: PackedUnMove) -> Self {
+        Self::from_u16(m.0)
+    }
+}
+
+impl From<u16> for PackedUnMove {
+    #[inline]
+    fn from(packed: u16) -> Self {
+        Self(packed)
+    }
+}
+
+impl From<PackedUnMove> for u16 {
+    #[inline]
+    fn from(m: PackedUnMove) -> Self {
+        m.0
+    }
+}
+
+impl fmt::Debug for PackedUnMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&UnMove::from(*self), f)
+    }
 }
 
 impl fmt::Debug for UnMove {
@@ -261,6 +604,78 @@ impl fmt::Debug for UnMove {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_from_move() {
+        for (m, retro_uci) in [
+            (
+                Move::Normal {
+                    role: Role::Knight,
+                    from: Square::G1,
+                    capture: None,
+                    to: Square::F3,
+                    promotion: None,
+                },
+                "f3g1",
+            ),
+            (
+                Move::Normal {
+                    role: Role::Rook,
+                    from: Square::A1,
+                    capture: Some(Role::Queen),
+                    to: Square::A8,
+                    promotion: None,
+                },
+                "Qa8a1",
+            ),
+            (
+                Move::Normal {
+                    role: Role::Pawn,
+                    from: Square::B7,
+                    capture: None,
+                    to: Square::B8,
+                    promotion: Some(Role::Knight),
+                },
+                "Ub8b7",
+            ),
+            (
+                Move::Normal {
+                    role: Role::Pawn,
+                    from: Square::B2,
+                    capture: Some(Role::Rook),
+                    to: Square::A1,
+                    promotion: Some(Role::Queen),
+                },
+                "URa1b2",
+            ),
+            (
+                Move::EnPassant {
+                    from: Square::E5,
+                    to: Square::D6,
+                },
+                "Ed6e5",
+            ),
+            (
+                Move::Castle {
+                    king: Square::E8,
+                    rook: Square::A8,
+                },
+                "Ce8a8",
+            ),
+        ] {
+            assert_eq!(
+                UnMove::from_move(&m),
+                Some(UnMove::from_retro_uci(retro_uci).unwrap())
+            );
+        }
+        assert_eq!(
+            UnMove::from_move(&Move::Put {
+                role: Role::Pawn,
+                to: Square::E4
+            }),
+            None
+        );
+    }
+
     #[test]
     fn test_parse_retro_uci_simple_move() {
         let simple_move: UnMove = UnMove::from_retro_uci("e2e4").unwrap();
@@ -306,15 +721,44 @@ mod tests {
         assert_eq!(UnMove::from_retro_uci("EQe3d4"), e);
     }
 
+    #[test]
+    fn test_parse_retro_uci_uncastling() {
+        let uncastling: UnMove = UnMove::from_retro_uci("Ce8h8").unwrap();
+        assert_eq!(uncastling.from, Square::E8);
+        assert_eq!(uncastling.to, Square::H8);
+        assert!(uncastling.is_uncastling());
+        assert_eq!(uncastling.uncapture(), None);
+        assert_eq!(uncastling.castling_side(), Some(CastlingSide::KingSide));
+        assert_eq!(UnMove::from_retro_uci("CRe1a1"), Err(ParseRetroUciError));
+    }
+
     #[test]
     fn test_to_uci() {
-        for x in &["e2e4", "Pe2e4", "Ue8e7", "Ee3d4", "Qa1a2", "Ba1a2", "Nd4d5"] {
+        for x in &[
+            "e2e4", "Pe2e4", "Ue8e7", "Ee3d4", "Qa1a2", "Ba1a2", "Nd4d5", "Ce1a1",
+        ] {
             let unmove: UnMove = UnMove::from_retro_uci(x).unwrap();
             assert_eq!(*x, &unmove.to_retro_uci());
             assert_eq!(format!("{unmove:?}"), *x);
         }
     }
 
+    #[test]
+    fn test_packed() {
+        for x in &[
+            "e2e4", "Pe2e4", "Ue8e7", "UPe8d7", "UQb1a2", "Ee3d4", "Qa1a2", "Ba1a2", "Nd4d5",
+            "Ce1a1", "Rh8h1",
+        ] {
+            let unmove = UnMove::from_retro_uci(x).unwrap();
+            let packed = PackedUnMove::from(&unmove);
+            assert_eq!(UnMove::from(packed), unmove);
+            assert_eq!(format!("{packed:?}"), *x);
+        }
+        for packed in 0..=u16::MAX {
+            assert_eq!(UnMove::from_u16(packed).to_u16(), packed);
+        }
+    }
+
     #[test]
     fn test_mirror() {
         assert_eq!(
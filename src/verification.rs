@@ -0,0 +1,306 @@
+//! Brute force verification of [`RetroBoard::legal_unmoves`] against the forward move generation of `shakmaty`.
+//!
+//! Every predecessor candidate is built by hand, and kept if `shakmaty` finds a legal move leading back to the position.
+//! As in [`RetroBoard`], castling rights are only added back by uncastling.
+
+use shakmaty::{
+    attacks, Bitboard, CastlingSide, Chess, FromSetup, Move, Position, PositionError, Rank, Role,
+    Setup, Square,
+};
+
+use crate::{RetroBoard, UnMove};
+
+/// Differences between [`RetroBoard::legal_unmoves`] and [`brute_force_unmoves`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    /// Unmoves to a legal predecessor which are not generated.
+    pub missing: Vec<UnMove>,
+    /// Generated unmoves without a legal predecessor.
+    pub extra: Vec<UnMove>,
+}
+
+impl Report {
+    /// Returns `true` if the generated unmoves are exactly the ones found by brute force.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// Compare [`RetroBoard::legal_unmoves`] with [`brute_force_unmoves`].
+/// # Examples
+/// ```
+/// use retroboard::{verification::verify, RetroBoard};
+///
+/// let r = RetroBoard::new("8/8/8/8/R7/2Q5/1B6/k1K5 b - - 0 1", "PNBRQ", "PNBRQ").unwrap();
+/// assert!(verify(&r).is_ok());
+/// ```
+#[must_use]
+pub fn verify(r: &RetroBoard) -> Report {
+    let expected = brute_force_unmoves(r);
+    let generated = r.legal_unmoves();
+    Report {
+        missing: expected
+            .iter()
+            .filter(|m| !generated.contains(m))
+            .cloned()
+            .collect(),
+        extra: generated
+            .into_iter()
+            .filter(|m| !expected.contains(m))
+            .collect(),
+    }
+}
+
+/// Unmoves to every predecessor from which a legal `shakmaty` move leads back to the position.
+/// This is very slow, and only meant to check [`RetroBoard::legal_unmoves`].
+#[must_use]
+pub fn brute_force_unmoves(r: &RetroBoard) -> Vec<UnMove> {
+    if r.is_start_of_game() {
+        return Vec::new();
+    }
+    let setup = Setup::from(*r);
+    candidates(r, &setup)
+        .into_iter()
+        .filter(|(predecessor, m)| leads_back(r, &setup, predecessor.clone(), m))
+        .map(|(_, m)| UnMove::from_move(&m).expect("Candidates are never drops"))
+        .collect()
+}
+
+/// All the predecessors obtained by moving back one of our pieces to an empty square,
+/// optionally uncapturing or unpromoting, and all the positions before castling.
+fn candidates(r: &RetroBoard, setup: &Setup) -> Vec<(Setup, Move)> {
+    let us = r.retro_turn();
+    let board = r.board();
+    let pockets = r.pockets();
+    let opponent_pocket = pockets.color(!us);
+    let mut captures = vec![None];
+    captures.extend(opponent_pocket.into_iter().map(Some));
+    let predecessor = |board, ep_square, castling_rights| Setup {
+        board,
+        turn: us,
+        castling_rights,
+        ep_square,
+        ..Setup::default()
+    };
+
+    let mut candidates = Vec::new();
+    for to in r.us() {
+        let piece = board.piece_at(to).unwrap();
+        let mut promotions = vec![None];
+        if pockets.color(us).unpromotion > 0
+            && us.relative_rank(Rank::Eighth) == to.rank()
+            && !matches!(piece.role, Role::Pawn | Role::King)
+        {
+            promotions.push(Some(piece.role));
+        }
+        for from in !board.occupied() {
+            for &promotion in &promotions {
+                for &capture in &captures {
+                    let mut board = board.clone();
+                    board.discard_piece_at(to);
+                    let role = if promotion.is_some() {
+                        Role::Pawn
+                    } else {
+                        piece.role
+                    };
+                    board.set_piece_at(from, role.of(us));
+                    if let Some(captured) = capture {
+                        board.set_piece_at(to, captured.of(!us));
+                    }
+                    candidates.push((
+                        predecessor(board, None, setup.castling_rights),
+                        Move::Normal {
+                            role,
+                            from,
+                            capture,
+                            to,
+                            promotion,
+                        },
+                    ));
+                }
+            }
+            let ep_pawn = Square::from_coords(to.file(), from.rank());
+            if piece.role == Role::Pawn
+                && opponent_pocket.pawn > 0
+                && ep_pawn != from
+                && board.piece_at(ep_pawn).is_none()
+            {
+                let mut board = board.clone();
+                board.discard_piece_at(to);
+                board.set_piece_at(from, piece);
+                board.set_piece_at(ep_pawn, (!us).pawn());
+                candidates.push((
+                    predecessor(board, Some(to), setup.castling_rights),
+                    Move::EnPassant { from, to },
+                ));
+            }
+        }
+    }
+
+    let backrank = Bitboard::from(us.backrank());
+    for side in CastlingSide::ALL {
+        let (king_to, rook_to) = (side.king_to(us), side.rook_to(us));
+        if board.piece_at(king_to) != Some(us.king()) || board.piece_at(rook_to) != Some(us.rook())
+        {
+            continue;
+        }
+        let mut uncastled = board.clone();
+        uncastled.discard_piece_at(king_to);
+        uncastled.discard_piece_at(rook_to);
+        for king in backrank & !uncastled.occupied() {
+            for rook in backrank & !uncastled.occupied() & !Bitboard::from(king) {
+                let mut board = uncastled.clone();
+                board.set_piece_at(king, us.king());
+                board.set_piece_at(rook, us.rook());
+                candidates.push((
+                    predecessor(board, None, setup.castling_rights.with(rook)),
+                    Move::Castle { king, rook },
+                ));
+            }
+        }
+    }
+    candidates
+}
+
+/// The move must be legal in the predecessor, and playing it must give back the position.
+fn leads_back(r: &RetroBoard, setup: &Setup, predecessor: Setup, m: &Move) -> bool {
+    if r.halfmove_clock() > 0 && m.is_zeroing() {
+        return false;
+    }
+    if let Some(ep_square) = setup.ep_square {
+        // only a pawn double push can have been played
+        let double_push = match *m {
+            Move::Normal {
+                role: Role::Pawn,
+                from,
+                to,
+                ..
+            } => attacks::between(from, to) == Bitboard::from(ep_square),
+            _ => false,
+        };
+        if !double_push {
+            return false;
+        }
+    }
+    let pos = match Chess::from_setup(predecessor, r.castling_mode())
+        .or_else(PositionError::ignore_impossible_check)
+        .or_else(PositionError::ignore_impossible_material)
+    {
+        Ok(pos) => pos,
+        Err(_) => return false,
+    };
+    if !pos.is_legal(m) {
+        return false;
+    }
+    let after = pos.play(m).expect("Legal move");
+    after.board() == &r.board() && after.castles().castling_rights() == setup.castling_rights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [(&str, &str, &str); 11] = [
+        ("8/8/8/8/R7/2Q5/1B6/k1K5 b - - 0 1", "PNBRQ", "PNBRQ"),
+        ("8/8/8/8/8/5k2/8/K3N2B b - - 0 1", "PNBRQ", "PNBRQ"),
+        ("8/8/8/8/8/2Q5/8/k6R b - - 0 1", "PNBRQ", "PNBRQ"),
+        (
+            "r3k2r/1P6/8/3pP3/8/8/8/R4RK1 b kq - 0 1",
+            "PNBRQ1",
+            "PNBRQ1",
+        ),
+        ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "", "NP"),
+        ("1R6/7k/8/8/8/8/8/1K6 b - - 0 1", "", "PQ3"),
+        (
+            "q4N2/1p5k/3P1b2/8/6P1/4Q3/3PB1r1/2KR4 b - - 0 1",
+            "PPNBRQ",
+            "PPPNBRQ",
+        ),
+        // unpromotion uncapturing a blocker
+        ("1k3Q1R/6q1/Kn6/nn3p2/8/8/8/8 b - -", "PPNBR2", "PPNBR2"),
+        // the king cannot unpromote
+        ("8/8/8/8/R7/B1Q5/1r6/k1K5 w - -", "PPNBRQ2", "PPNBQ2"),
+        // en passant in double check
+        (
+            "8/8/4P1r1/2R2k2/3p1rP1/1P1Kp2p/1R2P3/8 b - - 0 1",
+            "",
+            "PNBRQ",
+        ),
+        // the uncaptured pawn blocks the check
+        (
+            "b4Br1/5n2/kP3p2/5P2/pP2p1pP/P1p1p3/2P1B2R/4K2N b - -",
+            "NBRQ",
+            "PPNBRQ1",
+        ),
+    ];
+
+    fn verify_tree(r: &RetroBoard, depth: u32) {
+        let report = verify(r);
+        assert!(report.is_ok(), "{report:?} in {r:?}");
+        if let Err(inconsistency) = r.check_consistency() {
+            panic!(
+                "{inconsistency} in {r:?}, leading to {:?}",
+                inconsistency.positions.1
+            );
+        }
+        if depth > 0 {
+            for m in r.legal_unmoves() {
+                let mut child = *r;
+                child.push(&m);
+                verify_tree(&child, depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        for (fen, pocket_white, pocket_black) in POSITIONS {
+            verify_tree(
+                &RetroBoard::new(fen, pocket_white, pocket_black).unwrap(),
+                1,
+            );
+        }
+    }
+
+    /// Same unmoves picked at each run, so that failures can be reproduced.
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_verify_random_walks() {
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for (fen, pocket_white, pocket_black) in POSITIONS {
+            for _ in 0..30 {
+                let mut r = RetroBoard::new(fen, pocket_white, pocket_black).unwrap();
+                for _ in 0..10 {
+                    let report = verify(&r);
+                    assert!(report.is_ok(), "{report:?} in {r:?}");
+                    let moves = r.legal_unmoves();
+                    if moves.is_empty() {
+                        break;
+                    }
+                    r.push(&moves[(xorshift(&mut state) % moves.len() as u64) as usize]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_move_counters() {
+        for fen in [
+            "2k5/8/8/8/8/8/4P3/2KR4 w - - 1 2",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        ] {
+            verify_tree(
+                &RetroBoard::new_with_move_counters(fen, "PNBRQ", "PNBRQ").unwrap(),
+                2,
+            );
+        }
+    }
+}